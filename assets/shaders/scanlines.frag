@@ -0,0 +1,23 @@
+#version 450
+
+// Darkens every other row of pixels, loosely resembling the scanlines of a CRT.
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D src;
+layout(set = 0, binding = 1) uniform sampler src_sampler;
+layout(set = 0, binding = 2) uniform Globals {
+    vec2 resolution;
+    float time;
+} globals;
+layout(set = 0, binding = 3) uniform Params {
+    float strength; // 0.0 1.0 0.35
+    float lines; // 100.0 1000.0 400.0
+} params;
+
+void main() {
+    vec4 color = texture(sampler2D(src, src_sampler), v_tex_coords);
+    float wave = 0.5 + 0.5 * sin(v_tex_coords.y * params.lines * 3.14159265);
+    f_color = vec4(color.rgb * (1.0 - params.strength * wave), color.a);
+}
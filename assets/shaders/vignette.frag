@@ -0,0 +1,24 @@
+#version 450
+
+// Darkens the edges of the frame.
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D src;
+layout(set = 0, binding = 1) uniform sampler src_sampler;
+layout(set = 0, binding = 2) uniform Globals {
+    vec2 resolution;
+    float time;
+} globals;
+layout(set = 0, binding = 3) uniform Params {
+    float strength; // 0.0 2.0 0.8
+    float radius; // 0.1 1.5 0.75
+} params;
+
+void main() {
+    vec4 color = texture(sampler2D(src, src_sampler), v_tex_coords);
+    float dist = length(v_tex_coords - vec2(0.5)) / params.radius;
+    float vignette = clamp(1.0 - params.strength * dist * dist, 0.0, 1.0);
+    f_color = vec4(color.rgb * vignette, color.a);
+}
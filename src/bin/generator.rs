@@ -0,0 +1,84 @@
+//! Streams synthetic CBM 8032 frames in the on-wire serial format.
+//!
+//! Usage: `cbm8032_generator [--rate HZ] [--pattern counter|random] [--checksum none|crc8|crc16]
+//! [OUTPUT]`
+//!
+//! `OUTPUT` may be any writable path such as a pseudo-terminal (e.g. one end of a pair created
+//! with `socat -d -d pty,raw,echo=0 pty,raw,echo=0`) or a named pipe. If omitted, the stream is
+//! written to stdout.
+
+use cbm8032_to_vulkan::decode::Checksum;
+use cbm8032_to_vulkan::generate::{GeneratorSource, Pattern};
+use std::io::{self, Read, Write};
+
+const USAGE: &str = "usage: cbm8032_generator [--rate HZ] [--pattern counter|random] \
+                     [--checksum none|crc8|crc16] [OUTPUT]";
+
+fn main() {
+    let mut rate_hz = 60.0;
+    let mut pattern = Pattern::Counter;
+    let mut checksum = Checksum::None;
+    let mut output_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--rate" => {
+                rate_hz = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_else(|| exit_with_usage());
+            }
+            "--pattern" => {
+                pattern = match args.next().as_ref().map(|s| &s[..]) {
+                    Some("counter") => Pattern::Counter,
+                    Some("random") => Pattern::Random,
+                    _ => exit_with_usage(),
+                };
+            }
+            "--checksum" => {
+                checksum = match args.next().as_ref().map(|s| &s[..]) {
+                    Some("none") => Checksum::None,
+                    Some("crc8") => Checksum::Crc8,
+                    Some("crc16") => Checksum::Crc16,
+                    _ => exit_with_usage(),
+                };
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if output_path.is_none() => output_path = Some(arg),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let mut output: Box<dyn Write> = match output_path {
+        None => Box::new(io::stdout()),
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .unwrap_or_else(|err| {
+                    eprintln!("failed to open {}: {}", path, err);
+                    std::process::exit(1);
+                });
+            Box::new(file)
+        }
+    };
+
+    let mut source = GeneratorSource::with_checksum(pattern, rate_hz, checksum);
+    let mut buffer = [0u8; 256];
+    loop {
+        let len = source.read(&mut buffer).expect("failed to generate frame");
+        let res = output.write_all(&buffer[..len]).and_then(|_| output.flush());
+        if let Err(err) = res {
+            eprintln!("failed to write to output: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
@@ -1,3 +1,341 @@
+//! Runs the visualisation, or renders frames to image files without creating any windows.
+//!
+//! Usage: `cbm8032_to_vulkan [render (--capture FILE | --raw FILE | --text FILE)
+//! [--mode graphics|text] [--size WxH] [--until SECS] [--sequence] OUTPUT]`
+//! or `cbm8032_to_vulkan export --capture FILE [--rate HZ] [--size WxH] [--start SECS]
+//! [--end SECS] OUTPUT`
+//! or `cbm8032_to_vulkan clip --capture FILE [--rate HZ] [--scale N] [--start SECS] [--end SECS]
+//! OUTPUT`
+//!
+//! Without any arguments the visualisation and GUI windows are opened as usual.
+//!
+//! The `render` subcommand renders the given frame source with the colouration, phosphor
+//! persistence and stale buffer display of `assets/config.json`. `--capture` decodes a capture
+//! file, `--raw` reads a raw dump of the 2000 bytes of screen data and `--text` converts up to 25
+//! lines of ASCII text. `--mode` selects the character set used for raw and text sources. By
+//! default the last frame (or the last frame before `--until`) is written to `OUTPUT` as a PNG.
+//! With `--sequence`, every frame is written to a numbered image, e.g. `OUTPUT_000000.png`.
+//!
+//! The `export` subcommand renders a capture for video at a fixed frame rate (60 Hz by default),
+//! with deterministic timing. Each frame is written to a numbered image alongside a JSON manifest
+//! describing the timing of the sequence (e.g. `OUTPUT.json`). If `OUTPUT` is `-`, the frames are
+//! instead written to stdout as raw 8-bit RGBA video, ready to be piped to an encoder.
+//!
+//! The `clip` subcommand renders part of a capture to an animated GIF at the native resolution of
+//! 640x200 pixels scaled by `--scale`, at most 102 so that the width fits within a GIF. The rate
+//! and scale default to `clip` within the config.
+
+use cbm8032_to_vulkan::clip;
+use cbm8032_to_vulkan::conf::{self, Config};
+use cbm8032_to_vulkan::headless::{self, CaptureFrames, FixedRate, Manifest, ManifestFrame};
+use cbm8032_to_vulkan::headless::{Renderer, TimedFrame};
+use cbm8032_to_vulkan::raster::Rasteriser;
+use cbm8032_to_vulkan::vis::{Cbm8032Frame, Cbm8032FrameMode};
+use nannou::image;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const USAGE: &str = "usage: cbm8032_to_vulkan [render (--capture FILE | --raw FILE | --text FILE) \
+                     [--mode graphics|text] [--size WxH] [--until SECS] [--sequence] OUTPUT]\n   \
+                     or: cbm8032_to_vulkan export --capture FILE [--rate HZ] [--size WxH] \
+                     [--start SECS] [--end SECS] OUTPUT\n   \
+                     or: cbm8032_to_vulkan clip --capture FILE [--rate HZ] [--scale N] \
+                     [--start SECS] [--end SECS] OUTPUT";
+
+enum Input {
+    Capture(PathBuf),
+    Raw(PathBuf),
+    Text(PathBuf),
+}
+
 fn main() {
-    cbm8032_to_vulkan::run();
+    let mut args = std::env::args().skip(1);
+    match args.next().as_ref().map(|s| &s[..]) {
+        None => cbm8032_to_vulkan::run(),
+        Some("render") => render(args),
+        Some("export") => export(args),
+        Some("clip") => clip(args),
+        Some("-h") | Some("--help") => println!("{}", USAGE),
+        _ => exit_with_usage(),
+    }
+}
+
+fn render(mut args: impl Iterator<Item = String>) {
+    let mut input = None;
+    let mut mode = Cbm8032FrameMode::Graphics;
+    let mut size = None;
+    let mut until = None;
+    let mut sequence = false;
+    let mut output_path = None;
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--capture" => input = Some(Input::Capture(next_path(&mut args))),
+            "--raw" => input = Some(Input::Raw(next_path(&mut args))),
+            "--text" => input = Some(Input::Text(next_path(&mut args))),
+            "--mode" => {
+                mode = match args.next().as_ref().map(|s| &s[..]) {
+                    Some("graphics") => Cbm8032FrameMode::Graphics,
+                    Some("text") => Cbm8032FrameMode::Text,
+                    _ => exit_with_usage(),
+                };
+            }
+            "--size" => {
+                let wh = args.next().and_then(|s| parse_size(&s));
+                size = Some(wh.unwrap_or_else(|| exit_with_usage()));
+            }
+            "--until" => until = Some(next_secs(&mut args)),
+            "--sequence" => sequence = true,
+            _ if output_path.is_none() => output_path = Some(PathBuf::from(arg)),
+            _ => exit_with_usage(),
+        }
+    }
+    let input = input.unwrap_or_else(|| exit_with_usage());
+    let output_path = output_path.unwrap_or_else(|| exit_with_usage());
+
+    let (config, rasteriser) = load();
+    let size = size.unwrap_or_else(|| rasteriser.native_size());
+
+    let frames: Box<dyn Iterator<Item = io::Result<TimedFrame>>> = match input {
+        Input::Capture(path) => match CaptureFrames::open(path.clone(), &config.serial) {
+            Ok(frames) => Box::new(frames),
+            Err(err) => exit_with_error(&path, err),
+        },
+        Input::Raw(path) => match headless::read_raw(&path, mode) {
+            Ok(frame) => Box::new(std::iter::once(Ok(still(frame)))),
+            Err(err) => exit_with_error(&path, err),
+        },
+        Input::Text(path) => match headless::read_text(&path, mode) {
+            Ok(frame) => Box::new(std::iter::once(Ok(still(frame)))),
+            Err(err) => exit_with_error(&path, err),
+        },
+    };
+
+    let mut renderer = Renderer::new(rasteriser, config, size);
+    let mut last_image = None;
+    let mut count = 0;
+    for res in frames {
+        let timed = res.unwrap_or_else(|err| {
+            eprintln!("failed to read frame: {}", err);
+            std::process::exit(1);
+        });
+        if until.map(|until| timed.timestamp > until).unwrap_or(false) {
+            break;
+        }
+        let image = renderer.render(&timed.frame, timed.timestamp);
+        if sequence {
+            save(&image, &headless::numbered_path(&output_path, count));
+        } else {
+            last_image = Some(image);
+        }
+        count += 1;
+    }
+
+    match last_image {
+        Some(image) => save(&image, &output_path),
+        None if count == 0 => {
+            eprintln!("no frames were found within the source");
+            std::process::exit(1);
+        }
+        None => (),
+    }
+}
+
+fn export(mut args: impl Iterator<Item = String>) {
+    let mut capture_path = None;
+    let mut rate_hz = 60.0;
+    let mut size = None;
+    let mut start = None;
+    let mut end = None;
+    let mut output_path = None;
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--capture" => capture_path = Some(next_path(&mut args)),
+            "--rate" => {
+                rate_hz = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&hz: &f64| hz > 0.0)
+                    .unwrap_or_else(|| exit_with_usage());
+            }
+            "--size" => {
+                let wh = args.next().and_then(|s| parse_size(&s));
+                size = Some(wh.unwrap_or_else(|| exit_with_usage()));
+            }
+            "--start" => start = Some(next_secs(&mut args)),
+            "--end" => end = Some(next_secs(&mut args)),
+            _ if output_path.is_none() => output_path = Some(PathBuf::from(arg)),
+            _ => exit_with_usage(),
+        }
+    }
+    let capture_path = capture_path.unwrap_or_else(|| exit_with_usage());
+    let output_path = output_path.unwrap_or_else(|| exit_with_usage());
+
+    let (config, rasteriser) = load();
+    let size = size.unwrap_or_else(|| rasteriser.native_size());
+    let frames = CaptureFrames::open(capture_path.clone(), &config.serial)
+        .unwrap_or_else(|err| exit_with_error(&capture_path, err));
+    let end = end.unwrap_or_else(|| frames.duration());
+    let ticks = FixedRate::new(frames, rate_hz, end).start_at(start.unwrap_or_default());
+
+    // Either write raw video to stdout or an image sequence along with its manifest.
+    let stdout = io::stdout();
+    let mut raw_video = match output_path.to_str() {
+        Some("-") => Some(stdout.lock()),
+        _ => None,
+    };
+    let mut manifest = Manifest::new(rate_hz, size);
+    let mut renderer = Renderer::new(rasteriser, config, size);
+    for res in ticks {
+        let tick = res.unwrap_or_else(|err| exit_with_error(&capture_path, err));
+        let image = renderer.render(&tick.frame, tick.timestamp);
+        match raw_video {
+            Some(ref mut stdout) => {
+                if let Err(err) = stdout.write_all(&image) {
+                    eprintln!("failed to write to stdout: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                let path = headless::numbered_path(&output_path, manifest.frames.len());
+                save(&image, &path);
+                let file = path.file_name().unwrap().to_string_lossy().into_owned();
+                let timestamp_secs = tick.timestamp.as_secs_f64();
+                manifest.frames.push(ManifestFrame { file, timestamp_secs });
+            }
+        }
+    }
+
+    if raw_video.is_none() {
+        let manifest_path = output_path.with_extension("json");
+        let json = serde_json::to_string_pretty(&manifest).expect("failed to serialize manifest");
+        if let Err(err) = std::fs::write(&manifest_path, json) {
+            eprintln!("failed to write {}: {}", manifest_path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn clip(mut args: impl Iterator<Item = String>) {
+    let mut capture_path = None;
+    let mut rate_hz = None;
+    let mut scale = None;
+    let mut start = None;
+    let mut end = None;
+    let mut output_path = None;
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--capture" => capture_path = Some(next_path(&mut args)),
+            "--rate" => {
+                let hz = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&hz: &f64| hz > 0.0 && hz <= clip::MAX_FRAME_RATE);
+                rate_hz = Some(hz.unwrap_or_else(|| exit_with_usage()));
+            }
+            "--scale" => {
+                let n = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&n: &u32| n > 0 && n <= clip::MAX_SCALE);
+                scale = Some(n.unwrap_or_else(|| exit_with_usage()));
+            }
+            "--start" => start = Some(next_secs(&mut args)),
+            "--end" => end = Some(next_secs(&mut args)),
+            _ if output_path.is_none() => output_path = Some(PathBuf::from(arg)),
+            _ => exit_with_usage(),
+        }
+    }
+    let capture_path = capture_path.unwrap_or_else(|| exit_with_usage());
+    let output_path = output_path.unwrap_or_else(|| exit_with_usage());
+
+    let (config, rasteriser) = load();
+    let rate_hz = rate_hz.unwrap_or(config.clip.frame_rate as f64);
+    let scale = scale.unwrap_or(config.clip.scale).max(1);
+    // The config's clip settings are checked before creating the output file.
+    if let Err(err) = clip::check_frame_rate(rate_hz).and_then(|()| clip::check_scale(scale)) {
+        eprintln!("invalid clip settings in the config: {}", err);
+        std::process::exit(1);
+    }
+    let rate_hz = rate_hz.min(clip::MAX_FRAME_RATE);
+    let frames = CaptureFrames::open(capture_path.clone(), &config.serial)
+        .unwrap_or_else(|err| exit_with_error(&capture_path, err));
+    let end = end.unwrap_or_else(|| frames.duration());
+    let ticks = FixedRate::new(frames, rate_hz, end).start_at(start.unwrap_or_default());
+    let res = std::fs::File::create(&output_path).and_then(|file| {
+        let mut writer = io::BufWriter::new(file);
+        clip::write_gif(&mut writer, ticks, rasteriser, config, scale, rate_hz)?;
+        writer.flush()
+    });
+    if let Err(err) = res {
+        eprintln!("failed to write {}: {}", output_path.display(), err);
+        std::process::exit(1);
+    }
+}
+
+// Load the config and the character sheet from the project's assets directory.
+fn load() -> (Config, Rasteriser) {
+    let assets = nannou::app::find_assets_path().unwrap_or_else(|err| {
+        eprintln!("failed to find project `assets` directory: {}", err);
+        std::process::exit(1);
+    });
+    let config: Config = nannou::io::load_from_json(conf::path(&assets))
+        .ok()
+        .unwrap_or_else(Config::default);
+    let rasteriser = Rasteriser::open(&assets).unwrap_or_else(|err| {
+        eprintln!("failed to load the character sheet: {}", err);
+        std::process::exit(1);
+    });
+    (config, rasteriser)
+}
+
+// A single frame displayed from the beginning of the source.
+fn still(frame: Cbm8032Frame) -> TimedFrame {
+    let timestamp = Duration::from_secs(0);
+    TimedFrame { timestamp, frame }
+}
+
+fn next_path(args: &mut impl Iterator<Item = String>) -> PathBuf {
+    args.next().map(PathBuf::from).unwrap_or_else(|| exit_with_usage())
+}
+
+fn next_secs(args: &mut impl Iterator<Item = String>) -> Duration {
+    let secs = args
+        .next()
+        .and_then(|s| s.parse().ok())
+        .filter(|&secs: &f64| secs >= 0.0)
+        .unwrap_or_else(|| exit_with_usage());
+    Duration::from_secs_f64(secs)
+}
+
+// Parse a size of the form `WxH`.
+fn parse_size(s: &str) -> Option<[u32; 2]> {
+    let mut dims = s.split('x').map(|d| d.parse().ok().filter(|&d: &u32| d > 0));
+    match (dims.next(), dims.next(), dims.next()) {
+        (Some(Some(w)), Some(Some(h)), None) => Some([w, h]),
+        _ => None,
+    }
+}
+
+fn save(image: &image::RgbaImage, path: &Path) {
+    if let Some(parent) = path.parent() {
+        if let Err(err) = std::fs::create_dir_all(parent) {
+            eprintln!("failed to create {}: {}", parent.display(), err);
+            std::process::exit(1);
+        }
+    }
+    if let Err(err) = image.save(path) {
+        eprintln!("failed to write {}: {}", path.display(), err);
+        std::process::exit(1);
+    }
+}
+
+fn exit_with_error(path: &Path, err: io::Error) -> ! {
+    eprintln!("failed to read {}: {}", path.display(), err);
+    std::process::exit(1);
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
 }
@@ -0,0 +1,275 @@
+//! Items related to recording the raw serial stream to a capture file.
+//!
+//! A capture file begins with the 8-byte `MAGIC` header followed by a sequence of chunks, one per
+//! read from the frame source. Each chunk is laid out as:
+//!
+//! - `u64` (little endian) nanoseconds since the capture began, sampled from a monotonic clock.
+//! - `u32` (little endian) length of the chunk in bytes.
+//! - The raw bytes exactly as they were read from the source.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Identifies a file as a CBM 8032 serial capture and the version of the format.
+pub const MAGIC: [u8; 8] = *b"CBMCAP01";
+/// The extension used for capture files.
+pub const FILE_EXTENSION: &str = "cbmcap";
+
+// The size of the timestamp and length that precede each chunk's bytes.
+const CHUNK_HEADER_LEN: u64 = 12;
+
+/// Writes chunks of raw serial data to a capture file.
+pub struct Writer {
+    file: BufWriter<File>,
+    path: PathBuf,
+    start: Instant,
+}
+
+impl Writer {
+    /// Create the capture file at the given path and write the header.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = BufWriter::new(File::create(&path)?);
+        file.write_all(&MAGIC)?;
+        let start = Instant::now();
+        Ok(Writer { file, path, start })
+    }
+
+    /// The path to the capture file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a chunk of bytes, timestamped with the time elapsed since the capture began.
+    pub fn write_chunk(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let nanos = self.start.elapsed().as_nanos() as u64;
+        self.file.write_all(&nanos.to_le_bytes())?;
+        self.file.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.file.write_all(bytes)
+    }
+
+    /// Flush any buffered chunks to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Reads chunks of raw serial data from a capture file.
+pub struct Reader {
+    file: BufReader<File>,
+    path: PathBuf,
+    index: Vec<IndexEntry>,
+    next: usize,
+}
+
+/// A single chunk of bytes read from a capture file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    /// The time at which the chunk was read relative to the beginning of the capture.
+    pub timestamp: Duration,
+    /// The raw bytes exactly as they were read from the source.
+    pub bytes: Vec<u8>,
+}
+
+// The timestamp and file offset of a chunk, used for seeking.
+#[derive(Clone, Copy, Debug)]
+struct IndexEntry {
+    timestamp: Duration,
+    offset: u64,
+}
+
+impl Reader {
+    /// Open the capture file at the given path.
+    ///
+    /// The file is scanned once upon opening in order to index the position of every chunk.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(&path)?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            let desc = format!("{} is not a CBM 8032 capture file", path.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, desc));
+        }
+        // Index every complete chunk. A trailing partial chunk (e.g. due to the program closing
+        // mid-capture) is ignored.
+        let file_len = file.get_ref().metadata()?.len();
+        let mut index = vec![];
+        let mut offset = MAGIC.len() as u64;
+        while let Some((timestamp, len)) = read_chunk_header(&mut file)? {
+            let end = offset + CHUNK_HEADER_LEN + len as u64;
+            if end > file_len {
+                break;
+            }
+            index.push(IndexEntry { timestamp, offset });
+            file.seek(SeekFrom::Current(len as i64))?;
+            offset = end;
+        }
+        let mut reader = Reader {
+            file,
+            path,
+            index,
+            next: 0,
+        };
+        reader.seek(Duration::from_secs(0))?;
+        Ok(reader)
+    }
+
+    /// The path to the capture file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The timestamp of the last chunk in the capture.
+    pub fn duration(&self) -> Duration {
+        self.index
+            .last()
+            .map(|entry| entry.timestamp)
+            .unwrap_or_default()
+    }
+
+    /// The total number of chunks in the capture.
+    pub fn chunk_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Seek to the first chunk whose timestamp is at or after the given position.
+    pub fn seek(&mut self, position: Duration) -> io::Result<()> {
+        self.next = match self
+            .index
+            .binary_search_by(|entry| entry.timestamp.cmp(&position))
+        {
+            Ok(mut ix) => {
+                // Multiple chunks may share a timestamp, so find the first.
+                while ix > 0 && self.index[ix - 1].timestamp == position {
+                    ix -= 1;
+                }
+                ix
+            }
+            Err(ix) => ix,
+        };
+        if let Some(entry) = self.index.get(self.next) {
+            self.file.seek(SeekFrom::Start(entry.offset))?;
+        }
+        Ok(())
+    }
+
+    /// Read the next chunk from the capture, or `None` if the end has been reached.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        if self.next >= self.index.len() {
+            return Ok(None);
+        }
+        let (timestamp, len) = match read_chunk_header(&mut self.file)? {
+            None => return Ok(None),
+            Some(header) => header,
+        };
+        let mut bytes = vec![0u8; len as usize];
+        self.file.read_exact(&mut bytes)?;
+        self.next += 1;
+        Ok(Some(Chunk { timestamp, bytes }))
+    }
+}
+
+// Read the timestamp and length of the next chunk, or `None` if there are no more chunks.
+//
+// A partially written header is treated as the end of the capture.
+fn read_chunk_header<R: Read>(reader: &mut R) -> io::Result<Option<(Duration, u32)>> {
+    let mut header = [0u8; CHUNK_HEADER_LEN as usize];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) => return Ok(None),
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    let mut nanos = [0u8; 8];
+    let mut len = [0u8; 4];
+    nanos.copy_from_slice(&header[..8]);
+    len.copy_from_slice(&header[8..]);
+    let timestamp = Duration::from_nanos(u64::from_le_bytes(nanos));
+    let len = u32::from_le_bytes(len);
+    Ok(Some((timestamp, len)))
+}
+
+/// The directory in which captures are stored by default.
+pub fn directory(assets: &Path) -> PathBuf {
+    assets.join("captures")
+}
+
+/// Find the most recently modified capture file within `dir`, if any.
+pub fn latest_file_path(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == FILE_EXTENSION).unwrap_or(false))
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .max_by_key(|&(modified, _)| modified)
+        .map(|(_, path)| path)
+}
+
+/// Produce a path for a new capture file within `dir`, named by the current system time.
+pub fn new_file_path(dir: &Path) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    dir.join(format!("capture-{}.{}", secs, FILE_EXTENSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writer_reader_roundtrip() {
+        let name = format!("cbm8032_capture_{}.{}", std::process::id(), FILE_EXTENSION);
+        let path = std::env::temp_dir().join(name);
+        let chunks: [&[u8]; 3] = [b"first", b"", b"third"];
+        let mut writer = Writer::create(path.clone()).unwrap();
+        for (ix, bytes) in chunks.iter().enumerate() {
+            if ix > 0 {
+                std::thread::sleep(Duration::from_millis(2));
+            }
+            writer.write_chunk(bytes).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        // A trailing partial chunk is ignored.
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0u8; CHUNK_HEADER_LEN as usize - 1]).unwrap();
+        drop(file);
+
+        let mut reader = Reader::open(path.clone()).unwrap();
+        assert_eq!(reader.chunk_count(), chunks.len());
+        let mut read = vec![];
+        while let Some(chunk) = reader.next_chunk().unwrap() {
+            read.push(chunk);
+        }
+        let bytes: Vec<&[u8]> = read.iter().map(|chunk| &chunk.bytes[..]).collect();
+        assert_eq!(bytes, chunks);
+        assert!(read.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+        assert_eq!(reader.duration(), read[2].timestamp);
+
+        // Seeking between chunks resumes from the next one.
+        reader.seek(read[1].timestamp - Duration::from_nanos(1)).unwrap();
+        assert_eq!(reader.next_chunk().unwrap().as_ref(), Some(&read[1]));
+        reader.seek(read[2].timestamp).unwrap();
+        assert_eq!(reader.next_chunk().unwrap().as_ref(), Some(&read[2]));
+        assert_eq!(reader.next_chunk().unwrap(), None);
+        reader.seek(Duration::from_secs(0)).unwrap();
+        assert_eq!(reader.next_chunk().unwrap().as_ref(), Some(&read[0]));
+        reader.seek(reader.duration() + Duration::from_nanos(1)).unwrap();
+        assert_eq!(reader.next_chunk().unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
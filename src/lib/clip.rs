@@ -0,0 +1,312 @@
+//! Animated GIF clips of the visualisation, for sharing on the web.
+//!
+//! Clips are rendered at the PET's native resolution of 80×25 characters of 8×8 pixels, scaled by
+//! an integer factor and without the blank lines above and below the screen. As every pixel of
+//! the visualisation is the colouration scaled by the brightness of the phosphor, every image is
+//! encoded against a single palette of 256 brightness levels of the configured hue. The decay of
+//! the phosphor is approximated by quantising its brightness to these levels.
+
+use crate::conf::{self, Config};
+use crate::headless::{FixedRate, Renderer, TimedFrame};
+use crate::raster::{self, Rasteriser};
+use crate::vis::{self, Cbm8032Frame};
+use nannou::image::GenericImageView;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The width and height of each character in pixels on the PET's display.
+pub const GLYPH_SIZE: u32 = 8;
+/// The extension used for clip files.
+pub const FILE_EXTENSION: &str = "gif";
+/// The highest frame rate at which GIF delays are reliably respected by browsers.
+pub const MAX_FRAME_RATE: f64 = 50.0;
+/// The largest scale at which the width of a clip fits within the 16 bits allowed by GIF.
+pub const MAX_SCALE: u32 = u16::MAX as u32 / (vis::CHARS_PER_LINE as u32 * GLYPH_SIZE);
+
+// The gamma with which brightness is distributed across the palette, dedicating more levels to the
+// dim tail of the decay.
+const PALETTE_GAMMA: f32 = 2.2;
+
+/// The width and height of a clip at the given scale.
+pub fn size(scale: u32) -> [u32; 2] {
+    let w = vis::CHARS_PER_LINE as u32 * GLYPH_SIZE * scale;
+    let h = vis::DATA_LINES as u32 * GLYPH_SIZE * scale;
+    [w, h]
+}
+
+/// The palette of brightness levels of the given colouration, as `[r, g, b, r, g, b, ...]`.
+pub fn palette(colouration: &conf::Colouration) -> Vec<u8> {
+    let mut luminance = raster::Luminance::new(256, 1);
+    for (ix, pixel) in luminance.pixels_mut().enumerate() {
+        pixel[0] = level_brightness(ix as u8);
+    }
+    raster::colourise(&luminance, colouration)
+        .pixels()
+        .flat_map(|pixel| pixel.0[..3].to_vec())
+        .collect()
+}
+
+/// Render the given frames to an animated GIF that loops forever.
+///
+/// The frames are expected to be sampled at the given fixed rate, e.g. via `FixedRate`. Identical
+/// consecutive images are merged into one. Returns the number of images written, or an error if
+/// the scale exceeds `MAX_SCALE`.
+pub fn write_gif<W, I>(
+    writer: W,
+    frames: I,
+    rasteriser: Rasteriser,
+    config: Config,
+    scale: u32,
+    frame_rate: f64,
+) -> io::Result<usize>
+where
+    W: Write,
+    I: Iterator<Item = io::Result<TimedFrame>>,
+{
+    check_scale(scale)?;
+    let [w, h] = size(scale);
+    // The blank lines are rendered so that the characters are positioned as they are within the
+    // visualisation, then cropped.
+    let full_h = vis::TOTAL_LINES as u32 * GLYPH_SIZE * scale;
+    let top = (vis::BLANK_LINES / 2) as u32 * GLYPH_SIZE * scale;
+    let palette = palette(&config.colouration);
+    let mut encoder = gif::Encoder::new(writer, w as u16, h as u16, &palette).map_err(gif_error)?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+    let mut renderer = Renderer::new(rasteriser, config, [w, full_h]);
+    let mut pending: Option<(Vec<u8>, u16)> = None;
+    let mut written = 0;
+    for (tick, res) in frames.enumerate() {
+        let timed = res?;
+        let luminance = renderer.luminance(&timed.frame, timed.timestamp);
+        let indices: Vec<u8> = luminance
+            .view(0, top, w, h)
+            .pixels()
+            .map(|(_, _, pixel)| brightness_level(pixel[0]))
+            .collect();
+        let delay = delay_centisecs(tick as u64, frame_rate);
+        if let Some((ref prev_indices, ref mut prev_delay)) = pending {
+            if *prev_indices == indices {
+                *prev_delay = prev_delay.saturating_add(delay);
+                continue;
+            }
+        }
+        if let Some((indices, delay)) = pending.replace((indices, delay)) {
+            write_frame(&mut encoder, [w, h], &indices, delay)?;
+            written += 1;
+        }
+    }
+    match pending {
+        Some((indices, delay)) => write_frame(&mut encoder, [w, h], &indices, delay)?,
+        None => {
+            let desc = "no frames to write";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, desc));
+        }
+    }
+    Ok(written + 1)
+}
+
+/// Convert frames received at the given instants, e.g. via `serial::Handle::history`, to frames
+/// timed relative to the first.
+pub fn timed_frames(history: Vec<(Instant, Cbm8032Frame)>) -> Vec<TimedFrame> {
+    let start = match history.first() {
+        Some(&(instant, _)) => instant,
+        None => return vec![],
+    };
+    history
+        .into_iter()
+        .map(|(instant, frame)| {
+            let timestamp = instant.duration_since(start);
+            TimedFrame { timestamp, frame }
+        })
+        .collect()
+}
+
+/// Save the given frames to a new clip at the given path with the config's clip settings.
+///
+/// Loads the character sheet from the given assets directory. Returns the number of images
+/// written.
+pub fn save(
+    frames: Vec<TimedFrame>,
+    config: Config,
+    assets: &Path,
+    path: &Path,
+) -> io::Result<usize> {
+    let rasteriser = Rasteriser::open(assets)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    let end = frames.last().map(|f| f.timestamp).unwrap_or_default();
+    let frame_rate = config.clip.frame_rate as f64;
+    let scale = config.clip.scale.max(1);
+    // Checked before creating the file so that an invalid config leaves no empty clip behind.
+    check_frame_rate(frame_rate)?;
+    check_scale(scale)?;
+    let frame_rate = frame_rate.min(MAX_FRAME_RATE);
+    let ticks = FixedRate::new(frames.into_iter().map(Ok), frame_rate, end);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    let written = write_gif(&mut writer, ticks, rasteriser, config, scale, frame_rate)?;
+    writer.flush()?;
+    Ok(written)
+}
+
+/// The directory in which clips are saved from the GUI.
+pub fn directory(assets: &Path) -> PathBuf {
+    assets.join("clips")
+}
+
+/// A path for a new clip within `dir`, named after the current time.
+pub fn new_file_path(dir: &Path) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    dir.join(format!("clip-{}.{}", secs, FILE_EXTENSION))
+}
+
+/// Check that the given frame rate is finite and positive.
+pub fn check_frame_rate(frame_rate: f64) -> io::Result<()> {
+    if !frame_rate.is_finite() || frame_rate <= 0.0 {
+        let desc = format!("clip frame rate {} must be greater than zero", frame_rate);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, desc));
+    }
+    Ok(())
+}
+
+/// Check that the given scale does not exceed `MAX_SCALE`.
+pub fn check_scale(scale: u32) -> io::Result<()> {
+    if scale > MAX_SCALE {
+        let desc = format!("clip scale {} exceeds the maximum of {}", scale, MAX_SCALE);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, desc));
+    }
+    Ok(())
+}
+
+fn write_frame<W: Write>(
+    encoder: &mut gif::Encoder<W>,
+    [w, h]: [u32; 2],
+    indices: &[u8],
+    delay: u16,
+) -> io::Result<()> {
+    let mut frame = gif::Frame::from_indexed_pixels(w as u16, h as u16, indices, None);
+    frame.delay = delay;
+    encoder.write_frame(&frame).map_err(gif_error)
+}
+
+// The delay of the given tick in the hundredths of a second used by GIF.
+//
+// The delays are rounded such that the clip does not drift from the frame rate over time.
+fn delay_centisecs(tick: u64, frame_rate: f64) -> u16 {
+    let centisecs = |tick: u64| (tick as f64 * 100.0 / frame_rate).round() as u64;
+    (centisecs(tick + 1) - centisecs(tick)) as u16
+}
+
+// The brightness of the palette entry at the given index.
+fn level_brightness(index: u8) -> f32 {
+    (index as f32 / 255.0).powf(PALETTE_GAMMA)
+}
+
+// The index of the palette entry nearest to the given brightness.
+fn brightness_level(brightness: f32) -> u8 {
+    (brightness.clamp(0.0, 1.0).powf(1.0 / PALETTE_GAMMA) * 255.0).round() as u8
+}
+
+fn gif_error<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless;
+    use crate::phosphor::{Decay, Preset};
+    use crate::vis::Cbm8032FrameMode;
+    use std::time::Duration;
+
+    #[test]
+    fn brightness_levels_round_trip() {
+        for ix in 0..=255u8 {
+            assert_eq!(brightness_level(level_brightness(ix)), ix);
+        }
+        assert_eq!(brightness_level(2.0), 255);
+    }
+
+    #[test]
+    fn delays_do_not_drift() {
+        let total: u64 = (0..60).map(|tick| delay_centisecs(tick, 30.0) as u64).sum();
+        assert_eq!(total, 200);
+        assert_eq!(delay_centisecs(0, 25.0), 4);
+    }
+
+    #[test]
+    fn max_scale_fits_gif() {
+        assert!(size(MAX_SCALE)[0] <= u16::MAX as u32);
+        assert!(size(MAX_SCALE + 1)[0] > u16::MAX as u32);
+        let rasteriser = Rasteriser::open(&Path::new(env!("CARGO_MANIFEST_DIR")).join("assets"));
+        let frames = std::iter::empty();
+        let config = Config::default();
+        let res = write_gif(vec![], frames, rasteriser.unwrap(), config, MAX_SCALE + 1, 10.0);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn invalid_frame_rates_are_rejected() {
+        assert!(check_frame_rate(25.0).is_ok());
+        for &rate in &[0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let err = check_frame_rate(rate).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn timed_frames_are_relative_to_first() {
+        let start = Instant::now();
+        let frame = Cbm8032Frame::blank_graphics();
+        let history = vec![
+            (start, frame.clone()),
+            (start + Duration::from_millis(40), frame),
+        ];
+        let timed = timed_frames(history);
+        assert_eq!(timed[0].timestamp, Duration::from_secs(0));
+        assert_eq!(timed[1].timestamp, Duration::from_millis(40));
+    }
+
+    #[test]
+    fn gif_merges_identical_frames() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let rasteriser = Rasteriser::open(&assets).unwrap();
+        let text = headless::text_frame("HELLO", Cbm8032FrameMode::Graphics);
+        let frames = vec![
+            TimedFrame {
+                timestamp: Duration::from_millis(0),
+                frame: text.clone(),
+            },
+            TimedFrame {
+                timestamp: Duration::from_millis(100),
+                frame: text,
+            },
+        ];
+        // Without persistence, the second image is identical to the first.
+        let config = Config {
+            phosphor: conf::Phosphor {
+                preset: Preset::Custom,
+                custom: Decay {
+                    fast_secs: 0.0,
+                    slow_secs: 0.0,
+                    slow_weight: 0.0,
+                },
+            },
+            ..Default::default()
+        };
+        let ticks = frames.into_iter().map(Ok);
+        let mut bytes = vec![];
+        let written = write_gif(&mut bytes, ticks, rasteriser, config, 1, 10.0).unwrap();
+        assert_eq!(written, 1);
+        assert_eq!(&bytes[..6], b"GIF89a");
+    }
+}
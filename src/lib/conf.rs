@@ -1,4 +1,10 @@
+use crate::decode;
+use crate::generate;
+use crate::net;
+use crate::phosphor;
+use crate::serial;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 /// Runtime configuration parameters.
@@ -13,8 +19,32 @@ pub struct Config {
     pub on_startup: OnStartup,
     #[serde(default)]
     pub colouration: Colouration,
-    #[serde(default = "default::sustain")]
-    pub sustain: f32,
+    #[serde(default)]
+    pub phosphor: Phosphor,
+    /// Whether or not characters carried over from an earlier frame are shown in reverse video.
+    #[serde(default)]
+    pub show_stale_buffers: bool,
+    #[serde(default)]
+    pub crt: Crt,
+    /// Fullscreen post-processing passes applied in order after the main render.
+    #[serde(default)]
+    pub post_processing: Vec<PostPass>,
+    #[serde(default)]
+    pub capture: Capture,
+    #[serde(default)]
+    pub clip: Clip,
+    #[serde(default)]
+    pub source: Source,
+    #[serde(default)]
+    pub replay: Replay,
+    #[serde(default)]
+    pub generator: Generator,
+    #[serde(default)]
+    pub network_input: NetworkInput,
+    #[serde(default)]
+    pub network_output: NetworkOutput,
+    #[serde(default)]
+    pub serial: Serial,
 }
 
 /// Items that should run on startup.
@@ -26,6 +56,156 @@ pub struct OnStartup {
     pub serial: bool,
 }
 
+/// Configuration of the serial port connection.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Serial {
+    /// The port from which frames are read.
+    #[serde(default)]
+    pub port: serial::PortSelection,
+    /// The baud rate, framing, flow control and read behaviour of the serial line.
+    #[serde(default)]
+    pub line: serial::LineSettings,
+    /// The integrity check following each bufnum, for firmware using the checksum variant of the
+    /// protocol. Also applies to replayed captures.
+    #[serde(default)]
+    pub checksum: decode::Checksum,
+    /// Whether or not to publish the buffers received before a loss of sync, merged over the
+    /// previous frame, rather than discarding them.
+    #[serde(default)]
+    pub partial_frames: bool,
+}
+
+/// Emulation of the PET's CRT display, applied to the main render before any post-processing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Crt {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The amount of barrel distortion caused by the curvature of the glass.
+    #[serde(default = "default::crt::curvature")]
+    pub curvature: f32,
+    /// How much the gaps between scanlines are darkened.
+    #[serde(default = "default::crt::scanlines")]
+    pub scanlines: f32,
+    #[serde(default = "default::crt::mask")]
+    pub mask: CrtMask,
+    /// How strongly the phosphor mask pattern is shown.
+    #[serde(default = "default::crt::mask_strength")]
+    pub mask_strength: f32,
+    /// The amount of glow spreading from lit phosphors.
+    #[serde(default = "default::crt::bloom")]
+    pub bloom: f32,
+    /// How much the corners of the screen are darkened.
+    #[serde(default = "default::crt::vignette")]
+    pub vignette: f32,
+    /// The horizontal misalignment of the red and blue beams, in pixels.
+    #[serde(default = "default::crt::convergence")]
+    pub convergence: f32,
+}
+
+/// The pattern of the phosphors behind the glass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrtMask {
+    /// Triads of round phosphor dots, offset on alternating rows.
+    ShadowMask,
+    /// Continuous vertical stripes of phosphor.
+    ApertureGrille,
+}
+
+/// A fullscreen post-processing pass.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PostPass {
+    /// The fragment shader for the pass, relative to `assets/shaders`.
+    pub shader: String,
+    #[serde(default = "default::post_pass::enabled")]
+    pub enabled: bool,
+    /// Values for the float parameters declared by the shader. Parameters that are not listed use
+    /// the default declared by the shader.
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+}
+
+/// Recording of the raw serial stream.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    /// Whether or not every byte received over serial should be written to a capture file within
+    /// `assets/captures`.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Animated GIF clips of the visualisation, saved from the GUI or the `clip` subcommand.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Clip {
+    /// The factor by which the native resolution of 640x200 pixels is scaled, up to
+    /// `clip::MAX_SCALE`.
+    #[serde(default = "default::clip::scale")]
+    pub scale: u32,
+    /// The number of images per second, up to `clip::MAX_FRAME_RATE`.
+    #[serde(default = "default::clip::frame_rate")]
+    pub frame_rate: f32,
+}
+
+/// The source from which frames are received.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Source {
+    /// Frames are read from the first available USB serial port.
+    Serial,
+    /// Frames are read from a recorded capture file.
+    Replay,
+    /// Frames are synthesised by the `generate` module.
+    Generator,
+    /// Frames are received over the network from another instance.
+    Network,
+}
+
+/// Replay of a recorded serial capture.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    /// The capture file to replay, relative to `assets/captures`.
+    ///
+    /// If `None`, the most recent capture is replayed.
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default = "default::replay::speed")]
+    pub speed: f32,
+    #[serde(default)]
+    pub looping: bool,
+}
+
+/// Synthetic frame generation for testing without the hardware attached.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Generator {
+    /// The rate at which frames are produced in Hz.
+    #[serde(default = "default::generator::rate")]
+    pub rate: f32,
+    #[serde(default = "default::generator::pattern")]
+    pub pattern: generate::Pattern,
+}
+
+/// Receiving of frames over the network.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetworkInput {
+    #[serde(default = "default::network::transport")]
+    pub transport: net::Transport,
+    /// For UDP, the local address (or multicast group) on which to receive frames. For TCP, the
+    /// address of the sender to connect to.
+    #[serde(default = "default::network::input_address")]
+    pub address: String,
+}
+
+/// Republishing of decoded frames to the network so that other instances may share the source.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetworkOutput {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default::network::transport")]
+    pub transport: net::Transport,
+    /// For UDP, the address (or multicast group) to which frames are sent. For TCP, the local
+    /// address on which to listen for subscribers.
+    #[serde(default = "default::network::output_address")]
+    pub address: String,
+}
+
 /// Colouration of the visualisation.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Colouration {
@@ -35,8 +215,16 @@ pub struct Colouration {
     pub saturation: f32,
     #[serde(default = "default::colouration::brightness")]
     pub brightness: f32,
-    #[serde(default = "default::colouration::alpha")]
-    pub alpha: f32,
+}
+
+/// The persistence of the emulated phosphor.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Phosphor {
+    #[serde(default = "default::phosphor::preset")]
+    pub preset: phosphor::Preset,
+    /// The decay used by the `Custom` preset.
+    #[serde(default = "default::phosphor::custom")]
+    pub custom: phosphor::Decay,
 }
 
 impl Colouration {
@@ -51,7 +239,90 @@ impl Default for Colouration {
             hue: default::colouration::hue(),
             saturation: default::colouration::saturation(),
             brightness: default::colouration::brightness(),
-            alpha: default::colouration::alpha(),
+        }
+    }
+}
+
+impl Phosphor {
+    /// The decay of the selected preset.
+    pub fn decay(&self) -> phosphor::Decay {
+        self.preset.decay().unwrap_or(self.custom)
+    }
+}
+
+impl Default for Phosphor {
+    fn default() -> Self {
+        Phosphor {
+            preset: default::phosphor::preset(),
+            custom: default::phosphor::custom(),
+        }
+    }
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Crt {
+            enabled: false,
+            curvature: default::crt::curvature(),
+            scanlines: default::crt::scanlines(),
+            mask: default::crt::mask(),
+            mask_strength: default::crt::mask_strength(),
+            bloom: default::crt::bloom(),
+            vignette: default::crt::vignette(),
+            convergence: default::crt::convergence(),
+        }
+    }
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Serial
+    }
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Replay {
+            file: None,
+            speed: default::replay::speed(),
+            looping: false,
+        }
+    }
+}
+
+impl Default for Clip {
+    fn default() -> Self {
+        Clip {
+            scale: default::clip::scale(),
+            frame_rate: default::clip::frame_rate(),
+        }
+    }
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator {
+            rate: default::generator::rate(),
+            pattern: default::generator::pattern(),
+        }
+    }
+}
+
+impl Default for NetworkInput {
+    fn default() -> Self {
+        NetworkInput {
+            transport: default::network::transport(),
+            address: default::network::input_address(),
+        }
+    }
+}
+
+impl Default for NetworkOutput {
+    fn default() -> Self {
+        NetworkOutput {
+            enabled: false,
+            transport: default::network::transport(),
+            address: default::network::output_address(),
         }
     }
 }
@@ -61,7 +332,18 @@ impl Default for Config {
         Self {
             on_startup: Default::default(),
             colouration: Default::default(),
-            sustain: default::sustain(),
+            phosphor: Default::default(),
+            show_stale_buffers: false,
+            crt: Default::default(),
+            post_processing: vec![],
+            capture: Default::default(),
+            clip: Default::default(),
+            source: Default::default(),
+            replay: Default::default(),
+            generator: Default::default(),
+            network_input: Default::default(),
+            network_output: Default::default(),
+            serial: Default::default(),
         }
     }
 }
@@ -72,6 +354,16 @@ pub fn path(assets: &Path) -> PathBuf {
 }
 
 pub mod default {
+    pub mod clip {
+        pub fn scale() -> u32 {
+            2
+        }
+
+        pub fn frame_rate() -> f32 {
+            25.0
+        }
+    }
+
     pub mod colouration {
         use nannou::prelude::*;
         fn default_lin_srgb() -> LinSrgb {
@@ -93,13 +385,96 @@ pub mod default {
         pub fn brightness() -> f32 {
             default_hsv().value
         }
+    }
 
-        pub fn alpha() -> f32 {
-            1.0
+    pub mod crt {
+        use crate::conf::CrtMask;
+
+        pub fn curvature() -> f32 {
+            0.1
+        }
+
+        pub fn scanlines() -> f32 {
+            0.5
+        }
+
+        pub fn mask() -> CrtMask {
+            CrtMask::ApertureGrille
+        }
+
+        pub fn mask_strength() -> f32 {
+            0.25
+        }
+
+        pub fn bloom() -> f32 {
+            0.35
+        }
+
+        pub fn vignette() -> f32 {
+            0.3
+        }
+
+        pub fn convergence() -> f32 {
+            0.5
+        }
+    }
+
+    pub mod generator {
+        use crate::generate::Pattern;
+
+        pub fn rate() -> f32 {
+            60.0
+        }
+
+        pub fn pattern() -> Pattern {
+            Pattern::Counter
+        }
+    }
+
+    pub mod network {
+        use crate::net::{self, Transport};
+
+        pub fn transport() -> Transport {
+            Transport::Udp
+        }
+
+        /// By default frames are received from the multicast group to which they are broadcast,
+        /// so that instances on the local network interoperate without configuration.
+        pub fn input_address() -> String {
+            multicast_group()
+        }
+
+        /// By default frames are shared via a multicast group on the local network.
+        pub fn output_address() -> String {
+            multicast_group()
+        }
+
+        fn multicast_group() -> String {
+            format!("239.255.80.32:{}", net::DEFAULT_PORT)
+        }
+    }
+
+    pub mod post_pass {
+        pub fn enabled() -> bool {
+            true
         }
     }
 
-    pub fn sustain() -> f32 {
-        0.5
+    pub mod phosphor {
+        use crate::phosphor::{Decay, Preset};
+
+        pub fn preset() -> Preset {
+            Preset::P39
+        }
+
+        pub fn custom() -> Decay {
+            preset().decay().expect("default preset must not be custom")
+        }
+    }
+
+    pub mod replay {
+        pub fn speed() -> f32 {
+            1.0
+        }
     }
 }
@@ -0,0 +1,764 @@
+//! The state machine for decoding CBM 8032 frames from the raw serial byte stream.
+//!
+//! Each frame on the wire consists of:
+//!
+//! 1. `SYNC_ZEROS` consecutive zero bytes.
+//! 2. `DATA_BUFFERS_PER_FRAME` buffers of `DATA_PER_BUFFER` bytes of screen data, each followed by
+//!    its one-based buffer number (the "bufnum").
+//! 3. A final mode buffer of `DATA_PER_BUFFER` bytes followed by `MODE_BUFNUM`. The first byte of
+//!    the mode buffer is `0` for graphics mode and non-zero for text mode.
+//!
+//! An optional variant of the protocol follows each bufnum with a big-endian CRC-8 or CRC-16 of
+//! the buffer's data and bufnum (see `Checksum`). Buffers that fail the check are reported via
+//! `DecodeEvent::BufferCorrupted` and discarded, leaving the previous frame's contents in place.
+//!
+//! Each decoded frame carries a `stale` mask describing the buffers that were not received as a
+//! part of the frame. With partial recovery enabled, losing sync mid-frame produces a
+//! `DecodeEvent::PartialFrame` with the buffers received so far merged over the previous frame.
+//!
+//! Each frame event is preceded by a `DecodeEvent::BuffersChanged` describing the buffers whose
+//! data differs from the previously produced frame, allowing consumers to only touch what changed.
+
+use crate::vis;
+use serde::{Deserialize, Serialize};
+
+/// The number of consecutive zeros that precede each frame.
+pub const SYNC_ZEROS: u32 = 41;
+/// The number of bytes within each buffer, excluding the trailing bufnum.
+pub const DATA_PER_BUFFER: u32 = 40;
+/// The number of buffers containing screen data within each frame.
+pub const DATA_BUFFERS_PER_FRAME: u32 = 50;
+/// The bufnum of the buffer describing the frame's mode.
+pub const MODE_BUFNUM: u32 = DATA_BUFFERS_PER_FRAME + 1;
+/// A `BufferMask` with a bit set for every buffer of screen data.
+pub const ALL_BUFFERS: vis::BufferMask = (1 << DATA_BUFFERS_PER_FRAME) - 1;
+
+/// The integrity check following each bufnum on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Checksum {
+    /// The original protocol, where only the bufnum is checked.
+    None,
+    /// CRC-8 with polynomial `0x07` and an initial value of `0x00`.
+    Crc8,
+    /// CRC-16/CCITT-FALSE with polynomial `0x1021` and an initial value of `0xFFFF`.
+    Crc16,
+}
+
+/// Decodes frames from the raw serial byte stream.
+pub struct Decoder {
+    state: State,
+    bufnum: u32,
+    count: u32,
+    buffer: [u8; DATA_PER_BUFFER as usize],
+    checksum: Checksum,
+    checksum_bytes: [u8; 2],
+    partial_recovery: bool,
+    // The buffers of screen data received since the preamble.
+    received: vis::BufferMask,
+    // The buffers of screen data that changed since the last frame was produced.
+    dirty: vis::BufferMask,
+    screen_buffer: Box<vis::Cbm8032FrameData>,
+    mode: vis::Cbm8032FrameMode,
+    events: Vec<DecodeEvent>,
+}
+
+/// Events produced by the `Decoder` while decoding the byte stream.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeEvent {
+    /// The sync preamble was received and the decoder is now reading buffers.
+    SyncAcquired,
+    /// An unexpected bufnum was received and the decoder has returned to waiting for the preamble.
+    SyncLost {
+        /// The bufnum that was expected.
+        bufnum: u32,
+        /// The number of bytes received for the current buffer.
+        count: u32,
+        /// The byte that was received in place of the expected bufnum.
+        byte: u8,
+    },
+    /// The checksum of a buffer did not match its contents, so the buffer was discarded.
+    BufferCorrupted {
+        /// The bufnum of the corrupted buffer.
+        bufnum: u32,
+    },
+    /// Sync was lost part way through a frame while partial recovery was enabled.
+    ///
+    /// The frame contains the buffers received before the loss of sync merged over the previous
+    /// frame, with the remaining buffers marked as stale.
+    PartialFrame(vis::Cbm8032Frame),
+    /// The data of the given buffers differs from that of the previously produced frame.
+    ///
+    /// Produced immediately before the `FrameComplete` or `PartialFrame` event containing the
+    /// changes.
+    BuffersChanged(vis::BufferMask),
+    /// The mode buffer described a different mode to the previous frame.
+    ModeChanged(vis::Cbm8032FrameMode),
+    /// All buffers of a frame were received.
+    FrameComplete(vis::Cbm8032Frame),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    CountingZeros,
+    InSync,
+    // Reading the checksum following a buffer's bufnum.
+    ReadingChecksum,
+}
+
+impl Decoder {
+    /// Create a new decoder waiting for the sync preamble.
+    pub fn new() -> Self {
+        Self::with_checksum(Checksum::None)
+    }
+
+    /// Create a new decoder for the protocol variant with the given checksum.
+    pub fn with_checksum(checksum: Checksum) -> Self {
+        Decoder {
+            state: State::CountingZeros,
+            bufnum: 0,
+            count: 0,
+            buffer: [0u8; DATA_PER_BUFFER as usize],
+            checksum,
+            checksum_bytes: [0u8; 2],
+            partial_recovery: false,
+            received: 0,
+            dirty: 0,
+            screen_buffer: Box::new([0u8; vis::CBM_8032_FRAME_DATA_LEN]),
+            mode: vis::Cbm8032FrameMode::Graphics,
+            events: vec![],
+        }
+    }
+
+    /// Decode the given bytes, producing any events that occurred in the order that they occurred.
+    pub fn push<'a>(&'a mut self, bytes: &[u8]) -> impl Iterator<Item = DecodeEvent> + 'a {
+        for &byte in bytes {
+            self.handle_received_byte(byte);
+        }
+        self.events.drain(..)
+    }
+
+    /// Whether or not the decoder has received the sync preamble and is reading buffers.
+    pub fn is_in_sync(&self) -> bool {
+        self.state != State::CountingZeros
+    }
+
+    /// The checksum expected to follow each bufnum.
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    /// Switch to the protocol variant with the given checksum.
+    ///
+    /// The decoder returns to waiting for the sync preamble.
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = checksum;
+        self.state = State::CountingZeros;
+        self.count = 0;
+    }
+
+    /// Whether or not partial frames are produced upon losing sync.
+    pub fn partial_recovery(&self) -> bool {
+        self.partial_recovery
+    }
+
+    /// Enable or disable producing partial frames upon losing sync.
+    pub fn set_partial_recovery(&mut self, enabled: bool) {
+        self.partial_recovery = enabled;
+    }
+
+    /// The mode described by the most recently received mode buffer.
+    pub fn mode(&self) -> vis::Cbm8032FrameMode {
+        self.mode
+    }
+
+    /// The screen data as of the most recently received buffer.
+    pub fn screen(&self) -> &vis::Cbm8032FrameData {
+        &self.screen_buffer
+    }
+
+    fn handle_received_buffer(&mut self) {
+        if self.bufnum > 0 {
+            if self.bufnum < MODE_BUFNUM {
+                let bufidx = self.bufnum - 1;
+                let screen_start = (bufidx * DATA_PER_BUFFER) as usize;
+                let screen_end = screen_start + DATA_PER_BUFFER as usize;
+                let screen_slice = &mut self.screen_buffer[screen_start..screen_end];
+                if screen_slice != &self.buffer[..] {
+                    screen_slice.copy_from_slice(&self.buffer);
+                    self.dirty |= 1 << bufidx;
+                }
+                self.received |= 1 << bufidx;
+            } else {
+                let mode = byte_to_mode(self.buffer[0]);
+                if mode != self.mode {
+                    self.mode = mode;
+                    self.events.push(DecodeEvent::ModeChanged(mode));
+                }
+            }
+        }
+    }
+
+    fn handle_received_byte(&mut self, byte: u8) {
+        match self.state {
+            State::CountingZeros => {
+                if byte == 0 {
+                    self.count += 1;
+                    if self.count == SYNC_ZEROS {
+                        self.state = State::InSync;
+                        self.bufnum = 1;
+                        self.count = 0;
+                        self.received = 0;
+                        self.events.push(DecodeEvent::SyncAcquired);
+                    }
+                } else {
+                    self.count = 0;
+                }
+            }
+            State::InSync => {
+                if self.count < DATA_PER_BUFFER {
+                    self.buffer[self.count as usize] = byte;
+                    self.count += 1;
+                } else if byte == self.bufnum as u8 {
+                    if self.checksum == Checksum::None {
+                        self.handle_received_buffer();
+                        self.finish_buffer();
+                    } else {
+                        self.state = State::ReadingChecksum;
+                        self.count = 0;
+                    }
+                } else {
+                    self.events.push(DecodeEvent::SyncLost {
+                        bufnum: self.bufnum,
+                        count: self.count,
+                        byte,
+                    });
+                    if self.partial_recovery && self.received != 0 {
+                        let frame = self.take_frame();
+                        self.events.push(DecodeEvent::PartialFrame(frame));
+                    }
+                    self.state = State::CountingZeros;
+                    self.count = 0;
+                }
+            }
+            State::ReadingChecksum => {
+                self.checksum_bytes[self.count as usize] = byte;
+                self.count += 1;
+                let len = self.checksum.wire_len();
+                if self.count as usize == len {
+                    let expected = self.checksum.compute_buffer(&self.buffer, self.bufnum as u8);
+                    let received = self.checksum_bytes[..len]
+                        .iter()
+                        .fold(0u16, |acc, &b| (acc << 8) | b as u16);
+                    if received == expected {
+                        self.handle_received_buffer();
+                    } else {
+                        let bufnum = self.bufnum;
+                        self.events.push(DecodeEvent::BufferCorrupted { bufnum });
+                    }
+                    self.state = State::InSync;
+                    self.finish_buffer();
+                }
+            }
+        }
+    }
+
+    // Move on to the next buffer, completing the frame if this was the mode buffer.
+    fn finish_buffer(&mut self) {
+        self.bufnum += 1;
+        self.count = 0;
+        if self.bufnum == MODE_BUFNUM + 1 {
+            self.state = State::CountingZeros;
+            let frame = self.take_frame();
+            self.events.push(DecodeEvent::FrameComplete(frame));
+        }
+    }
+
+    // The current screen, with any buffers not received since the preamble marked as stale.
+    //
+    // Reports and resets the buffers that changed since the last frame was produced.
+    fn take_frame(&mut self) -> vis::Cbm8032Frame {
+        if self.dirty != 0 {
+            self.events.push(DecodeEvent::BuffersChanged(self.dirty));
+            self.dirty = 0;
+        }
+        let mut frame = vis::Cbm8032Frame::new(self.mode, self.screen_buffer.clone());
+        frame.stale = ALL_BUFFERS & !self.received;
+        frame
+    }
+}
+
+impl Checksum {
+    /// The number of bytes occupied by the checksum on the wire.
+    pub fn wire_len(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc8 => 1,
+            Checksum::Crc16 => 2,
+        }
+    }
+
+    /// Compute the checksum of a buffer, covering its data followed by its bufnum.
+    pub fn compute_buffer(self, data: &[u8], bufnum: u8) -> u16 {
+        let bytes = data.iter().chain(std::iter::once(&bufnum));
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc8 => crc8(bytes) as u16,
+            Checksum::Crc16 => crc16(bytes),
+        }
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum::None
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-8 with polynomial `0x07` and an initial value of `0x00`.
+pub fn crc8<'a, I>(bytes: I) -> u8
+where
+    I: IntoIterator<Item = &'a u8>,
+{
+    bytes.into_iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// CRC-16/CCITT-FALSE with polynomial `0x1021` and an initial value of `0xFFFF`.
+pub fn crc16<'a, I>(bytes: I) -> u16
+where
+    I: IntoIterator<Item = &'a u8>,
+{
+    bytes.into_iter().fold(0xFFFFu16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// The mode described by the first byte of the mode buffer.
+pub fn byte_to_mode(byte: u8) -> vis::Cbm8032FrameMode {
+    match byte {
+        0 => vis::Cbm8032FrameMode::Graphics,
+        _ => vis::Cbm8032FrameMode::Text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vis::{Cbm8032Frame, Cbm8032FrameMode, CBM_8032_FRAME_DATA_LEN};
+
+    // Encode a frame in the on-wire format.
+    fn encode(mode_byte: u8, data: &[u8]) -> Vec<u8> {
+        assert_eq!(data.len(), CBM_8032_FRAME_DATA_LEN);
+        let mut bytes = vec![0u8; SYNC_ZEROS as usize];
+        for (ix, buffer) in data.chunks(DATA_PER_BUFFER as usize).enumerate() {
+            bytes.extend_from_slice(buffer);
+            bytes.push(ix as u8 + 1);
+        }
+        bytes.push(mode_byte);
+        bytes.extend(std::iter::repeat(0).take(DATA_PER_BUFFER as usize - 1));
+        bytes.push(MODE_BUFNUM as u8);
+        bytes
+    }
+
+    // Screen data that never contains a zero, so that it can't be mistaken for the preamble.
+    fn test_data(seed: u8) -> Vec<u8> {
+        (0..CBM_8032_FRAME_DATA_LEN)
+            .map(|ix| (ix as u8).wrapping_add(seed) | 1)
+            .collect()
+    }
+
+    fn frame(mode: Cbm8032FrameMode, data: &[u8]) -> Cbm8032Frame {
+        let mut frame_data = Box::new([0u8; CBM_8032_FRAME_DATA_LEN]);
+        frame_data.copy_from_slice(data);
+        Cbm8032Frame::new(mode, frame_data)
+    }
+
+    fn frames(events: &[DecodeEvent]) -> Vec<&Cbm8032Frame> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                DecodeEvent::FrameComplete(frame) => Some(frame),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sync_requires_41_zeros() {
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&[0; 40]).collect();
+        assert!(events.is_empty());
+        assert!(!decoder.is_in_sync());
+        let events: Vec<_> = decoder.push(&[0]).collect();
+        assert_eq!(events, vec![DecodeEvent::SyncAcquired]);
+        assert!(decoder.is_in_sync());
+    }
+
+    #[test]
+    fn non_zero_byte_resets_preamble() {
+        let mut decoder = Decoder::new();
+        let mut bytes = vec![0u8; 40];
+        bytes.push(7);
+        bytes.extend(vec![0u8; 40]);
+        assert_eq!(decoder.push(&bytes).count(), 0);
+        assert!(!decoder.is_in_sync());
+        let events: Vec<_> = decoder.push(&[0]).collect();
+        assert_eq!(events, vec![DecodeEvent::SyncAcquired]);
+    }
+
+    #[test]
+    fn decodes_graphics_frame() {
+        let data = test_data(0);
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&encode(0, &data)).collect();
+        let expected = frame(Cbm8032FrameMode::Graphics, &data);
+        assert_eq!(
+            events,
+            vec![
+                DecodeEvent::SyncAcquired,
+                DecodeEvent::BuffersChanged(ALL_BUFFERS),
+                DecodeEvent::FrameComplete(expected),
+            ]
+        );
+        assert!(!decoder.is_in_sync());
+    }
+
+    #[test]
+    fn decodes_text_frame_and_reports_mode_changes() {
+        let data = test_data(3);
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&encode(1, &data)).collect();
+        let expected = frame(Cbm8032FrameMode::Text, &data);
+        assert_eq!(
+            events,
+            vec![
+                DecodeEvent::SyncAcquired,
+                DecodeEvent::ModeChanged(Cbm8032FrameMode::Text),
+                DecodeEvent::BuffersChanged(ALL_BUFFERS),
+                DecodeEvent::FrameComplete(expected),
+            ]
+        );
+        assert_eq!(decoder.mode(), Cbm8032FrameMode::Text);
+
+        // Any non-zero byte indicates text mode, so no change should be reported.
+        let events: Vec<_> = decoder.push(&encode(255, &data)).collect();
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, DecodeEvent::ModeChanged(_))));
+
+        let events: Vec<_> = decoder.push(&encode(0, &data)).collect();
+        assert!(events.contains(&DecodeEvent::ModeChanged(Cbm8032FrameMode::Graphics)));
+    }
+
+    #[test]
+    fn mode_buffer_does_not_touch_screen() {
+        let data = test_data(5);
+        let mut decoder = Decoder::new();
+        decoder.push(&encode(1, &data)).for_each(drop);
+        assert_eq!(&decoder.screen()[..], &data[..]);
+    }
+
+    #[test]
+    fn bufnum_trailer_bytes_are_not_screen_data() {
+        let data = test_data(9);
+        let bytes = encode(0, &data);
+        // The first bufnum trailer follows the preamble and first buffer.
+        let first_trailer = SYNC_ZEROS as usize + DATA_PER_BUFFER as usize;
+        assert_eq!(bytes[first_trailer], 1);
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        let decoded = frames(&events);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(&decoded[0].data[..], &data[..]);
+    }
+
+    #[test]
+    fn wrong_bufnum_loses_sync() {
+        let data = test_data(0);
+        let mut bytes = encode(0, &data);
+        // Corrupt the trailer of the third buffer.
+        let trailer = SYNC_ZEROS as usize + 3 * (DATA_PER_BUFFER as usize + 1) - 1;
+        assert_eq!(bytes[trailer], 3);
+        bytes[trailer] = 42;
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        assert_eq!(events[0], DecodeEvent::SyncAcquired);
+        assert_eq!(
+            events[1],
+            DecodeEvent::SyncLost {
+                bufnum: 3,
+                count: DATA_PER_BUFFER,
+                byte: 42,
+            }
+        );
+        assert!(frames(&events).is_empty());
+        assert!(!decoder.is_in_sync());
+    }
+
+    #[test]
+    fn wrong_mode_bufnum_loses_sync() {
+        let data = test_data(0);
+        let mut bytes = encode(1, &data);
+        *bytes.last_mut().unwrap() = 0;
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        assert_eq!(
+            events.last(),
+            Some(&DecodeEvent::SyncLost {
+                bufnum: MODE_BUFNUM,
+                count: DATA_PER_BUFFER,
+                byte: 0,
+            })
+        );
+        assert!(frames(&events).is_empty());
+        // The mode is only applied once the mode buffer's bufnum is verified.
+        assert_eq!(decoder.mode(), Cbm8032FrameMode::Graphics);
+    }
+
+    #[test]
+    fn recovers_from_mid_frame_desync() {
+        let first = test_data(1);
+        let second = test_data(2);
+        let third = test_data(3);
+        // Truncate the first frame mid-buffer. The zeros of the following preamble are consumed
+        // while completing the truncated buffer, so sync is only regained at the third frame.
+        let first_bytes = encode(0, &first);
+        let mut bytes = first_bytes[..first_bytes.len() / 2].to_vec();
+        bytes.extend(encode(0, &second));
+        bytes.extend(encode(0, &third));
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, DecodeEvent::SyncLost { .. })));
+        let decoded = frames(&events);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(&decoded[0].data[..], &third[..]);
+    }
+
+    #[test]
+    fn decodes_consecutive_frames() {
+        let mut bytes = encode(0, &test_data(1));
+        bytes.extend(encode(1, &test_data(2)));
+        bytes.extend(encode(0, &test_data(3)));
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        let decoded = frames(&events);
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(&decoded[2].data[..], &test_data(3)[..]);
+    }
+
+    #[test]
+    fn chunking_does_not_affect_decoding() {
+        let mut bytes = encode(1, &test_data(4));
+        bytes.extend(encode(0, &test_data(6)));
+        let mut decoder = Decoder::new();
+        let expected: Vec<_> = decoder.push(&bytes).collect();
+        for chunk_len in &[1, 7, 41, 256] {
+            let mut decoder = Decoder::new();
+            let events: Vec<_> = bytes
+                .chunks(*chunk_len)
+                .flat_map(|chunk| decoder.push(chunk).collect::<Vec<_>>())
+                .collect();
+            assert_eq!(events, expected);
+        }
+    }
+
+    #[test]
+    fn crc_check_values() {
+        // The standard check values for the ASCII string "123456789".
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    // Encode a frame in the protocol variant with the given checksum.
+    fn encode_with_checksum(checksum: Checksum, mode: Cbm8032FrameMode, data: &[u8]) -> Vec<u8> {
+        crate::encode::encode_frame_with_checksum(&frame(mode, data), checksum)
+    }
+
+    #[test]
+    fn corrupted_buffer_keeps_previous_rows() {
+        for &checksum in &[Checksum::Crc8, Checksum::Crc16] {
+            let first = test_data(1);
+            let second = test_data(2);
+            let mut bytes = encode_with_checksum(checksum, Cbm8032FrameMode::Graphics, &first);
+            let mut second_bytes =
+                encode_with_checksum(checksum, Cbm8032FrameMode::Graphics, &second);
+            // Corrupt a data byte of the fourth buffer.
+            let buffer_len = DATA_PER_BUFFER as usize + 1 + checksum.wire_len();
+            second_bytes[SYNC_ZEROS as usize + 3 * buffer_len + 5] ^= 0x10;
+            bytes.extend(second_bytes);
+
+            let mut decoder = Decoder::with_checksum(checksum);
+            let events: Vec<_> = decoder.push(&bytes).collect();
+            assert!(events.contains(&DecodeEvent::BufferCorrupted { bufnum: 4 }));
+            let decoded = frames(&events);
+            assert_eq!(decoded.len(), 2);
+            let start = 3 * DATA_PER_BUFFER as usize;
+            let end = start + DATA_PER_BUFFER as usize;
+            assert_eq!(&decoded[1].data[..start], &second[..start]);
+            assert_eq!(&decoded[1].data[start..end], &first[start..end]);
+            assert_eq!(&decoded[1].data[end..], &second[end..]);
+        }
+    }
+
+    #[test]
+    fn corrupted_mode_buffer_keeps_previous_mode() {
+        let checksum = Checksum::Crc16;
+        let data = test_data(1);
+        let mut bytes = encode_with_checksum(checksum, Cbm8032FrameMode::Text, &data);
+        // Corrupt the final byte of the checksum of the mode buffer.
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        let mut decoder = Decoder::with_checksum(checksum);
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        assert!(events.contains(&DecodeEvent::BufferCorrupted {
+            bufnum: MODE_BUFNUM
+        }));
+        assert_eq!(frames(&events)[0].mode, Cbm8032FrameMode::Graphics);
+    }
+
+    #[test]
+    fn checksum_chunking_does_not_affect_decoding() {
+        let checksum = Checksum::Crc8;
+        let mut bytes = encode_with_checksum(checksum, Cbm8032FrameMode::Text, &test_data(4));
+        bytes.extend(encode_with_checksum(checksum, Cbm8032FrameMode::Graphics, &test_data(6)));
+        let mut decoder = Decoder::with_checksum(checksum);
+        let expected: Vec<_> = decoder.push(&bytes).collect();
+        assert_eq!(frames(&expected).len(), 2);
+        for chunk_len in &[1, 7, 42, 256] {
+            let mut decoder = Decoder::with_checksum(checksum);
+            let events: Vec<_> = bytes
+                .chunks(*chunk_len)
+                .flat_map(|chunk| decoder.push(chunk).collect::<Vec<_>>())
+                .collect();
+            assert_eq!(events, expected);
+        }
+    }
+
+    #[test]
+    fn corrupted_buffers_are_marked_stale() {
+        let checksum = Checksum::Crc8;
+        let mut bytes = encode_with_checksum(checksum, Cbm8032FrameMode::Graphics, &test_data(1));
+        let buffer_len = DATA_PER_BUFFER as usize + 1 + checksum.wire_len();
+        bytes[SYNC_ZEROS as usize + 9 * buffer_len] ^= 0x01;
+        let mut decoder = Decoder::with_checksum(checksum);
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        assert_eq!(frames(&events)[0].stale, 1 << 9);
+    }
+
+    #[test]
+    fn partial_frame_on_sync_loss() {
+        let first = test_data(1);
+        let second = test_data(2);
+        let mut bytes = encode(0, &first);
+        let mut second_bytes = encode(0, &second);
+        // Corrupt the trailer of the fifth buffer, so that only the first four are received.
+        let trailer = SYNC_ZEROS as usize + 5 * (DATA_PER_BUFFER as usize + 1) - 1;
+        second_bytes[trailer] = 42;
+        bytes.extend(second_bytes);
+
+        // Without recovery, the partially received frame is discarded.
+        let mut decoder = Decoder::new();
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, DecodeEvent::PartialFrame(_))));
+
+        let mut decoder = Decoder::new();
+        decoder.set_partial_recovery(true);
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        let partial = events
+            .iter()
+            .find_map(|event| match event {
+                DecodeEvent::PartialFrame(frame) => Some(frame),
+                _ => None,
+            })
+            .expect("no partial frame");
+        let split = 4 * DATA_PER_BUFFER as usize;
+        assert_eq!(&partial.data[..split], &second[..split]);
+        assert_eq!(&partial.data[split..], &first[split..]);
+        assert_eq!(partial.stale, ALL_BUFFERS & !0b1111);
+        assert!(!partial.is_stale(split - 1));
+        assert!(partial.is_stale(split));
+    }
+
+    #[test]
+    fn no_partial_frame_without_received_buffers() {
+        let mut bytes = encode(0, &test_data(1));
+        let trailer = SYNC_ZEROS as usize + DATA_PER_BUFFER as usize;
+        bytes[trailer] = 42;
+        let mut decoder = Decoder::new();
+        decoder.set_partial_recovery(true);
+        let events: Vec<_> = decoder.push(&bytes).collect();
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, DecodeEvent::PartialFrame(_))));
+    }
+
+    #[test]
+    fn only_changed_buffers_are_reported() {
+        let first = test_data(1);
+        let mut second = first.clone();
+        second[0] = 0xFF;
+        second[DATA_PER_BUFFER as usize * 7 + 3] = 0xFF;
+        let mut decoder = Decoder::new();
+        decoder.push(&encode(0, &first)).for_each(drop);
+        let events: Vec<_> = decoder.push(&encode(0, &second)).collect();
+        assert!(events.contains(&DecodeEvent::BuffersChanged(1 | 1 << 7)));
+
+        // Nothing is reported for an identical frame.
+        let events: Vec<_> = decoder.push(&encode(0, &second)).collect();
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, DecodeEvent::BuffersChanged(_))));
+        assert_eq!(frames(&events).len(), 1);
+    }
+
+    #[test]
+    fn frame_deltas_rebuild_frames() {
+        let mut decoder = Decoder::new();
+        let mut rendered = vis::Cbm8032Frame::blank_graphics();
+        let mut pending = None;
+        for seed in 0..3 {
+            let mut data = test_data(1);
+            data[DATA_PER_BUFFER as usize * seed] = 0xFF;
+            let mut dirty = 0;
+            for event in decoder.push(&encode(0, &data)) {
+                match event {
+                    DecodeEvent::BuffersChanged(buffers) => dirty |= buffers,
+                    DecodeEvent::FrameComplete(frame) => {
+                        let delta = vis::FrameDelta::new(&frame, dirty);
+                        let len = dirty.count_ones() * DATA_PER_BUFFER;
+                        assert_eq!(delta.data.len(), len as usize);
+                        match pending {
+                            None => pending = Some(delta),
+                            Some(ref mut pending) => pending.merge(delta),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        // The merged deltas are equivalent to applying each in turn.
+        pending.unwrap().apply(&mut rendered);
+        let mut expected = test_data(1);
+        expected[DATA_PER_BUFFER as usize * 2] = 0xFF;
+        assert_eq!(&rendered.data[..], &expected[..]);
+        assert_eq!(rendered.mode, vis::Cbm8032FrameMode::Graphics);
+    }
+}
@@ -0,0 +1,130 @@
+//! Encoding of `Cbm8032Frame`s into the on-wire serial format understood by the `Decoder`.
+//!
+//! See the `decode` module documentation for a description of the format.
+
+use crate::decode::{Checksum, DATA_PER_BUFFER, MODE_BUFNUM, SYNC_ZEROS};
+use crate::vis;
+
+/// The total number of bytes in a single encoded frame.
+pub const ENCODED_FRAME_LEN: usize =
+    SYNC_ZEROS as usize + MODE_BUFNUM as usize * (DATA_PER_BUFFER as usize + 1);
+
+/// The total number of bytes in a single frame encoded with the given checksum.
+pub fn encoded_frame_len(checksum: Checksum) -> usize {
+    ENCODED_FRAME_LEN + MODE_BUFNUM as usize * checksum.wire_len()
+}
+
+/// Encode the given frame, producing the exact byte sequence sent by the CBM 8032 hardware.
+pub fn encode_frame(frame: &vis::Cbm8032Frame) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(ENCODED_FRAME_LEN);
+    encode_frame_into(frame, &mut bytes);
+    bytes
+}
+
+/// Encode the given frame, appending the bytes to the end of `bytes`.
+pub fn encode_frame_into(frame: &vis::Cbm8032Frame, bytes: &mut Vec<u8>) {
+    encode_frame_with_checksum_into(frame, Checksum::None, bytes);
+}
+
+/// Encode the given frame using the protocol variant with the given checksum.
+pub fn encode_frame_with_checksum(frame: &vis::Cbm8032Frame, checksum: Checksum) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(encoded_frame_len(checksum));
+    encode_frame_with_checksum_into(frame, checksum, &mut bytes);
+    bytes
+}
+
+/// Encode the given frame using the protocol variant with the given checksum, appending the bytes
+/// to the end of `bytes`.
+pub fn encode_frame_with_checksum_into(
+    frame: &vis::Cbm8032Frame,
+    checksum: Checksum,
+    bytes: &mut Vec<u8>,
+) {
+    bytes.extend((0..SYNC_ZEROS).map(|_| 0u8));
+    for (ix, buffer) in frame.data.chunks(DATA_PER_BUFFER as usize).enumerate() {
+        let bufnum = ix as u8 + 1;
+        bytes.extend_from_slice(buffer);
+        bytes.push(bufnum);
+        push_checksum(checksum, buffer, bufnum, bytes);
+    }
+    let mut mode_buffer = [0u8; DATA_PER_BUFFER as usize];
+    mode_buffer[0] = mode_to_byte(frame.mode);
+    bytes.extend_from_slice(&mode_buffer);
+    bytes.push(MODE_BUFNUM as u8);
+    push_checksum(checksum, &mode_buffer, MODE_BUFNUM as u8, bytes);
+}
+
+/// The byte used to describe the given mode within the mode buffer.
+pub fn mode_to_byte(mode: vis::Cbm8032FrameMode) -> u8 {
+    match mode {
+        vis::Cbm8032FrameMode::Graphics => 0,
+        vis::Cbm8032FrameMode::Text => 1,
+    }
+}
+
+// Append the big-endian checksum of the buffer if there is one.
+fn push_checksum(checksum: Checksum, data: &[u8], bufnum: u8, bytes: &mut Vec<u8>) {
+    let crc = checksum.compute_buffer(data, bufnum);
+    let crc_bytes = crc.to_be_bytes();
+    bytes.extend_from_slice(&crc_bytes[crc_bytes.len() - checksum.wire_len()..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{DecodeEvent, Decoder};
+    use crate::vis::{Cbm8032Frame, Cbm8032FrameMode, CBM_8032_FRAME_DATA_LEN};
+
+    fn test_frame(mode: Cbm8032FrameMode) -> Cbm8032Frame {
+        let mut data = Box::new([0u8; CBM_8032_FRAME_DATA_LEN]);
+        for (ix, byte) in data.iter_mut().enumerate() {
+            *byte = (ix % 256) as u8;
+        }
+        Cbm8032Frame::new(mode, data)
+    }
+
+    #[test]
+    fn encoded_layout() {
+        let frame = test_frame(Cbm8032FrameMode::Text);
+        let bytes = encode_frame(&frame);
+        assert_eq!(bytes.len(), ENCODED_FRAME_LEN);
+        assert!(bytes[..SYNC_ZEROS as usize].iter().all(|&b| b == 0));
+        let buffers = bytes[SYNC_ZEROS as usize..].chunks(DATA_PER_BUFFER as usize + 1);
+        for (ix, buffer) in buffers.enumerate() {
+            assert_eq!(*buffer.last().unwrap() as usize, ix + 1);
+        }
+        let mode_buffer_start = ENCODED_FRAME_LEN - DATA_PER_BUFFER as usize - 1;
+        assert_eq!(bytes[mode_buffer_start], 1);
+    }
+
+    #[test]
+    fn roundtrip_through_decoder() {
+        for &mode in &[Cbm8032FrameMode::Text, Cbm8032FrameMode::Graphics] {
+            let frame = test_frame(mode);
+            let mut decoder = Decoder::new();
+            let decoded: Vec<_> = decoder
+                .push(&encode_frame(&frame))
+                .filter_map(|event| match event {
+                    DecodeEvent::FrameComplete(frame) => Some(frame),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(decoded, vec![frame]);
+        }
+    }
+
+    #[test]
+    fn roundtrip_with_checksums() {
+        for &checksum in &[Checksum::Crc8, Checksum::Crc16] {
+            let frame = test_frame(Cbm8032FrameMode::Text);
+            let bytes = encode_frame_with_checksum(&frame, checksum);
+            assert_eq!(bytes.len(), encoded_frame_len(checksum));
+            let mut decoder = Decoder::with_checksum(checksum);
+            let events: Vec<_> = decoder.push(&bytes).collect();
+            assert_eq!(events.last(), Some(&DecodeEvent::FrameComplete(frame)));
+            assert!(!events
+                .iter()
+                .any(|event| matches!(event, DecodeEvent::BufferCorrupted { .. })));
+        }
+    }
+}
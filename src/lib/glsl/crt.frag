@@ -0,0 +1,100 @@
+// NOTE: This shader is compiled to SPIR-V at runtime via naga. If you update
+// this shader, be sure to also re-compile the bundled `crt_frag.spv` fallback
+// with the same version of naga. You can do so using `naga-cli` 0.13 with the
+// following command:
+// `naga crt.frag crt_frag.spv`
+
+#version 450
+
+// Each character of the PET is drawn with eight scanlines, matching `TOTAL_LINES` in `vis.rs`.
+const float SCANLINES = 27.0 * 8.0;
+const uint MASK_SHADOW_MASK = 0;
+const float PI = 3.14159265;
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D screen;
+layout(set = 0, binding = 1) uniform sampler screen_sampler;
+layout(set = 0, binding = 2) uniform Crt {
+    vec2 resolution;
+    float curvature;
+    float scanlines;
+    float mask_strength;
+    float bloom;
+    float vignette;
+    float convergence;
+    uint mask;
+} crt;
+
+vec3 sample_screen(vec2 uv) {
+    return texture(sampler2D(screen, screen_sampler), uv).rgb;
+}
+
+// Bend the coordinates outwards from the centre, as seen through the curved glass.
+vec2 barrel(vec2 uv) {
+    vec2 centred = uv * 2.0 - 1.0;
+    float r2 = dot(centred, centred);
+    centred *= (1.0 + crt.curvature * r2) / (1.0 + crt.curvature);
+    return centred * 0.5 + 0.5;
+}
+
+// Sample the screen with the red and blue beams slightly out of alignment.
+vec3 converge(vec2 uv) {
+    vec2 offset = vec2(crt.convergence / crt.resolution.x, 0.0);
+    float r = sample_screen(uv + offset).r;
+    float g = sample_screen(uv).g;
+    float b = sample_screen(uv - offset).b;
+    return vec3(r, g, b);
+}
+
+// The average of the screen surrounding the given coordinates, approximating the glow of the
+// phosphors spreading through the glass.
+vec3 glow(vec2 uv) {
+    vec2 px = 3.0 / crt.resolution;
+    vec3 sum = vec3(0.0);
+    for (int i = 0; i < 8; i++) {
+        float angle = float(i) * PI / 4.0;
+        vec2 dir = vec2(cos(angle), sin(angle));
+        sum += sample_screen(uv + dir * px);
+        sum += sample_screen(uv + dir * px * 2.0) * 0.5;
+    }
+    return sum / 12.0;
+}
+
+// The colour of the phosphor at the given pixel, from either the shadow mask or aperture grille.
+vec3 phosphor_mask(vec2 frag_coord) {
+    uint x = uint(frag_coord.x);
+    uint y = uint(frag_coord.y);
+    if (crt.mask == MASK_SHADOW_MASK) {
+        x += (y / 2) % 2 * 2;
+    }
+    vec3 mask = vec3(0.5);
+    uint channel = x % 3;
+    if (channel == 0) {
+        mask.r = 1.5;
+    } else if (channel == 1) {
+        mask.g = 1.5;
+    } else {
+        mask.b = 1.5;
+    }
+    return mix(vec3(1.0), mask, crt.mask_strength);
+}
+
+void main() {
+    vec2 uv = barrel(v_tex_coords);
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec3 rgb = converge(uv);
+    float beam = 0.5 - 0.5 * cos(2.0 * PI * uv.y * SCANLINES);
+    rgb *= 1.0 - crt.scanlines * (1.0 - beam);
+    rgb *= phosphor_mask(gl_FragCoord.xy);
+    rgb += glow(uv) * crt.bloom;
+
+    float edge = 16.0 * uv.x * uv.y * (1.0 - uv.x) * (1.0 - uv.y);
+    rgb *= mix(1.0, pow(edge, 0.5), crt.vignette);
+    f_color = vec4(rgb, 1.0);
+}
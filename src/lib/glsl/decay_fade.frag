@@ -0,0 +1,23 @@
+// NOTE: This shader is compiled to SPIR-V at runtime via naga. If you update
+// this shader, be sure to also re-compile the bundled `decay_fade_frag.spv`
+// fallback with the same version of naga. You can do so using `naga-cli` 0.13
+// with the following command:
+// `naga decay_fade.frag decay_fade_frag.spv`
+
+#version 450
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Data {
+    vec4 colouration;
+    // The factor by which each decay component is multiplied since the previous frame.
+    vec2 decay_factors;
+    // The brightness of each decay component after being struck by the beam.
+    vec2 decay_weights;
+} uniforms;
+
+// Decay each component of the phosphor by the time elapsed since the previous frame. The output
+// is multiplied with the existing decay image via blending.
+void main() {
+    f_color = vec4(uniforms.decay_factors, 1.0, 1.0);
+}
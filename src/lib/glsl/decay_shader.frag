@@ -1,7 +1,8 @@
-// NOTE: This shader requires being manually compiled to SPIR-V. If you update
-// this shader, be sure to also re-compile it and update `frag.spv`. You can do
-// so using `glslangValidator` with the following command:
-// `glslangValidator -V decay_shader.frag -o decay_frag.spv`
+// NOTE: This shader is compiled to SPIR-V at runtime via naga. If you update
+// this shader, be sure to also re-compile the bundled `decay_frag.spv` fallback
+// with the same version of naga. You can do so using `naga-cli` 0.13 with the
+// following command:
+// `naga decay_shader.frag decay_frag.spv`
 
 #version 450
 
@@ -10,16 +11,17 @@ layout(location = 0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform Data {
     vec4 colouration;
-    float sustain;
+    // The factor by which each decay component is multiplied since the previous frame.
+    vec2 decay_factors;
+    // The brightness of each decay component after being struck by the beam.
+    vec2 decay_weights;
 } uniforms;
 layout(set = 0, binding = 1) uniform texture2D char_sheet;
 layout(set = 0, binding = 2) uniform sampler texture_sampler;
 
+// Excite each component of the phosphor struck by the beam. This is blended with the decayed
+// components via the max operation.
 void main() {
     float l = texture(sampler2D(char_sheet, texture_sampler), v_char_sheet_tex_coords).r;
-    float a = uniforms.colouration.a;
-    if (l > 0.5) {
-        a = 1.0;
-    }
-    f_color = vec4(l, l, l, a);
+    f_color = vec4(uniforms.decay_weights * l, 0.0, 1.0);
 }
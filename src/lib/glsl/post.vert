@@ -0,0 +1,17 @@
+// NOTE: This shader is compiled to SPIR-V at runtime via naga. If you update
+// this shader, be sure to also re-compile the bundled `post_vert.spv` fallback
+// with the same version of naga. You can do so using `naga-cli` 0.13 with the
+// following command:
+// `naga post.vert post_vert.spv`
+
+#version 450
+
+// A single triangle covering the whole frame, used by each post-processing pass.
+layout(location = 0) out vec2 v_tex_coords;
+
+void main() {
+    // The vertices are wound counter-clockwise.
+    vec2 tex_coords = vec2(float(gl_VertexIndex & 2), float((gl_VertexIndex << 1) & 2));
+    v_tex_coords = tex_coords;
+    gl_Position = vec4(tex_coords.x * 2.0 - 1.0, 1.0 - tex_coords.y * 2.0, 0.0, 1.0);
+}
@@ -1,7 +1,8 @@
-// NOTE: This shader requires being manually compiled to SPIR-V. If you update
-// this shader, be sure to also re-compile it and update `frag.spv`. You can do
-// so using `glslangValidator` with the following command:
-// `glslangValidator -V shader.frag`
+// NOTE: This shader is compiled to SPIR-V at runtime via naga. If you update
+// this shader, be sure to also re-compile the bundled `frag.spv` fallback with
+// the same version of naga. You can do so using `naga-cli` 0.13 with the
+// following command:
+// `naga shader.frag frag.spv`
 
 #version 450
 
@@ -11,7 +12,10 @@ layout(location = 0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform Data {
     vec4 colouration;
-    float sustain;
+    // The factor by which each decay component is multiplied since the previous frame.
+    vec2 decay_factors;
+    // The brightness of each decay component after being struck by the beam.
+    vec2 decay_weights;
 } uniforms;
 layout(set = 0, binding = 1) uniform texture2D char_sheet;
 layout(set = 0, binding = 2) uniform texture2D decay;
@@ -19,7 +23,9 @@ layout(set = 0, binding = 3) uniform sampler texture_sampler;
 
 void main() {
     float char_sheet_color = texture(sampler2D(char_sheet, texture_sampler), v_char_sheet_tex_coords).r;
-    float decay_color = texture(sampler2D(decay, texture_sampler), v_decay_tex_coords).r * uniforms.sustain;
+    // The decay image holds the brightness of the fast and slow components of the phosphor.
+    vec2 decay_components = texture(sampler2D(decay, texture_sampler), v_decay_tex_coords).rg;
+    float decay_color = decay_components.r + decay_components.g;
     vec3 rgb = uniforms.colouration.rgb * max(char_sheet_color, decay_color);
     f_color = vec4(rgb, 1.0);
 }
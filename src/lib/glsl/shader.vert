@@ -1,24 +1,69 @@
-// NOTE: This shader requires being manually compiled to SPIR-V. If you update
-// this shader, be sure to also re-compile it and update `vert.spv`. You can do
-// so using `glslangValidator` with the following command:
-// `glslangValidator -V shader.vert`
+// NOTE: This shader is compiled to SPIR-V at runtime via naga. If you update
+// this shader, be sure to also re-compile the bundled `vert.spv` fallback with
+// the same version of naga. You can do so using `naga-cli` 0.13 with the
+// following command:
+// `naga shader.vert vert.spv`
 
 #version 450
 
+// The layout of the visualisation, matching the constants in `vis.rs`.
+const uint CHARS_PER_LINE = 80;
+const uint DATA_LINES = 25;
+const uint BLANK_LINES = 2;
+const uint TOTAL_LINES = DATA_LINES + BLANK_LINES;
+const uint FIRST_DATA_CHAR = BLANK_LINES / 2 * CHARS_PER_LINE;
+const uint DATA_CHARS = DATA_LINES * CHARS_PER_LINE;
+const uint BLANK_BYTE = 32;
+const uint CHAR_SHEET_COLS = 16;
+const uint CHAR_SHEET_ROWS = 32;
+const uint GRAPHICS_MODE_ROW_OFFSET = 0;
+const uint TEXT_MODE_ROW_OFFSET = 16;
+const uint MODE_GRAPHICS = 0;
+
 // The quad vertex positions.
 layout(location = 0) in vec2 position;
 layout(location = 1) in vec2 tex_coords;
 
-// The per-instance data.
-layout(location = 2) in vec2 position_offset;
-layout(location = 3) in vec2 tex_coords_offset;
-
 // Feed the offset texture coordinatees through to the frag shader.
 layout(location = 0) out vec2 v_char_sheet_tex_coords;
 // Also need to pass through coords for sampling from decay texture.
 layout(location = 1) out vec2 v_decay_tex_coords;
 
+// The mode followed by the raw screen bytes, packed four to each `uint`.
+layout(set = 1, binding = 0) readonly buffer Screen {
+    uint mode;
+    uint data[DATA_CHARS / 4];
+} screen;
+
+// The byte for the character at the given index, where the index includes the leading blank line.
+uint screen_byte(uint char_index) {
+    if (char_index < FIRST_DATA_CHAR || char_index >= FIRST_DATA_CHAR + DATA_CHARS) {
+        return BLANK_BYTE;
+    }
+    uint data_index = char_index - FIRST_DATA_CHAR;
+    uint word = screen.data[data_index / 4];
+    return (word >> ((data_index % 4) * 8)) & 0xFF;
+}
+
 void main() {
+    // Each instance is a single character of the screen.
+    uint char_index = uint(gl_InstanceIndex);
+    uint b = screen_byte(char_index);
+
+    // Find the character within the char sheet. See `vis::byte_to_char_sheet_col_row`.
+    uint row_offset = TEXT_MODE_ROW_OFFSET;
+    if (screen.mode == MODE_GRAPHICS) {
+        row_offset = GRAPHICS_MODE_ROW_OFFSET;
+    }
+    uint col = b % CHAR_SHEET_COLS;
+    uint row = row_offset + b / (CHAR_SHEET_ROWS / 2);
+    vec2 tex_coords_offset = vec2(float(col), float(row)) / vec2(CHAR_SHEET_COLS, CHAR_SHEET_ROWS);
+
+    // Find the position of the character. See `vis::serial_char_index_to_position_offset`.
+    uint x = char_index % CHARS_PER_LINE;
+    uint y = char_index / CHARS_PER_LINE;
+    vec2 position_offset = 2.0 * vec2(float(x), float(y)) / vec2(CHARS_PER_LINE, TOTAL_LINES);
+
     // Apply the tex coord offset into the character sheet for the instance.
     v_char_sheet_tex_coords = tex_coords + tex_coords_offset;
     // Convert vertex coords to UV coordinates for sampling from the decay texture.
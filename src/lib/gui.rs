@@ -1,18 +1,30 @@
 //! Various GUI parameters for runtime control.
 
 use crate::conf::Config;
+use crate::conf;
+use crate::decode;
 use crate::fps::Fps;
+use crate::net;
+use crate::phosphor;
+use crate::replay;
 use crate::serial;
 use crate::vis;
 use nannou::prelude::*;
 use nannou::ui::conrod_core::widget_ids;
 use nannou::ui::prelude::*;
+use serialport::SerialPortInfo;
 
 pub const COLUMN_W: Scalar = 240.0;
 pub const DEFAULT_WIDGET_H: Scalar = 30.0;
 pub const PAD: Scalar = 20.0;
 pub const WINDOW_WIDTH: u32 = (COLUMN_W + PAD * 2.0) as u32;
-pub const WINDOW_HEIGHT: u32 = 770;
+pub const WINDOW_HEIGHT: u32 = 810;
+
+// Common baud rates offered by the serial line settings.
+const BAUD_RATES: &[u32] = &[
+    9_600, 19_200, 38_400, 57_600, 115_200, 230_400, 460_800, 500_000, 921_600, 1_000_000,
+    1_500_000, 2_000_000, 3_000_000,
+];
 
 widget_ids! {
     pub struct Ids {
@@ -21,6 +33,12 @@ widget_ids! {
         fullscreen_on_startup_toggle,
         serial_on_startup_toggle,
         serial_on_toggle,
+        capture_toggle,
+        save_clip_button,
+        save_clip_status_text,
+        source_toggle,
+        serial_port_list,
+        broadcast_toggle,
         clear_frame_button,
         random_frame_button,
         vis_fps_text,
@@ -35,8 +53,45 @@ widget_ids! {
         hue_slider,
         saturation_slider,
         brightness_slider,
-        alpha_slider,
-        sustain_slider,
+        phosphor_text,
+        phosphor_preset_list,
+        phosphor_fast_slider,
+        phosphor_slow_slider,
+        phosphor_slow_weight_slider,
+        crt_text,
+        crt_toggle,
+        crt_mask_list,
+        crt_curvature_slider,
+        crt_scanlines_slider,
+        crt_mask_strength_slider,
+        crt_bloom_slider,
+        crt_vignette_slider,
+        crt_convergence_slider,
+        shader_text,
+        shader_status_text,
+        post_text,
+        post_status_text,
+        post_pass_toggles[],
+        post_param_sliders[],
+        replay_text,
+        replay_pause_button,
+        replay_loop_toggle,
+        replay_speed_slider,
+        replay_position_slider,
+        serial_line_text,
+        baud_rate_list,
+        data_bits_list,
+        parity_list,
+        stop_bits_list,
+        flow_control_list,
+        checksum_list,
+        partial_frames_toggle,
+        show_stale_toggle,
+        read_timeout_slider,
+        read_buffer_size_slider,
+        link_text,
+        link_reset_button,
+        link_stats_text,
         serial_port_info_text,
     }
 }
@@ -44,17 +99,24 @@ widget_ids! {
 /// Update the user interface.
 pub fn update(
     ref mut ui: UiCell,
-    ids: &Ids,
+    ids: &mut Ids,
     config: &mut Config,
     serial_on: &mut bool,
+    save_clip: &mut bool,
+    clip_status: Option<&str>,
     vis_fps: &Fps,
     serial_handle: Option<&serial::Handle>,
+    serial_ports: &[SerialPortInfo],
+    replay_controls: Option<&replay::Controls>,
+    network_stats: Option<&net::Stats>,
+    vis: &vis::Vis,
     frame: &mut vis::Cbm8032Frame,
 ) {
     widget::Canvas::new()
         .border(0.0)
         .rgb(0.1, 0.1, 0.1)
         .pad(PAD)
+        .scroll_kids_vertically()
         .set(ids.background, ui);
 
     // Title
@@ -119,6 +181,103 @@ pub fn update(
         *serial_on = !*serial_on;
     }
 
+    for _click in button()
+        .mid_left_of(ids.background)
+        .down(PAD * 0.5)
+        .label(if config.capture.enabled {
+            "Capture - ENABLED"
+        } else {
+            "Capture - DISABLED"
+        })
+        .color(if config.capture.enabled {
+            color::DARK_RED
+        } else {
+            color::BLACK
+        })
+        .set(ids.capture_toggle, ui)
+    {
+        config.capture.enabled = !config.capture.enabled;
+    }
+
+    for _click in button()
+        .mid_left_of(ids.background)
+        .down(PAD * 0.5)
+        .label("Save Clip")
+        .set(ids.save_clip_button, ui)
+    {
+        *save_clip = true;
+    }
+
+    if let Some(status) = clip_status {
+        widget::Text::new(status)
+            .down(PAD * 0.5)
+            .w(COLUMN_W)
+            .font_size(12)
+            .color(color::WHITE)
+            .set(ids.save_clip_status_text, ui);
+    }
+
+    for _click in button()
+        .mid_left_of(ids.background)
+        .down(PAD * 0.5)
+        .label(match config.source {
+            conf::Source::Serial => "Source - SERIAL",
+            conf::Source::Replay => "Source - REPLAY",
+            conf::Source::Generator => "Source - GENERATOR",
+            conf::Source::Network => "Source - NETWORK",
+        })
+        .set(ids.source_toggle, ui)
+    {
+        config.source = match config.source {
+            conf::Source::Serial => conf::Source::Replay,
+            conf::Source::Replay => conf::Source::Generator,
+            conf::Source::Generator => conf::Source::Network,
+            conf::Source::Network => conf::Source::Serial,
+        };
+    }
+
+    // The first entry is the first USB port, followed by all available ports.
+    let port_labels: Vec<String> = std::iter::once("Serial Port - FIRST USB".to_string())
+        .chain(serial_ports.iter().map(port_label))
+        .collect();
+    let selected = match config.serial.port {
+        serial::PortSelection::FirstUsb => Some(0),
+        ref selection => serial_ports
+            .iter()
+            .position(|info| selection.matches(info))
+            .map(|ix| ix + 1),
+    };
+    let unavailable_label = format!("{} (Not Found)", config.serial.port);
+    for new_selected in drop_down_list(&port_labels, selected)
+        .mid_left_of(ids.background)
+        .down(PAD * 0.5)
+        .label(&unavailable_label)
+        .set(ids.serial_port_list, ui)
+    {
+        config.serial.port = match new_selected {
+            0 => serial::PortSelection::FirstUsb,
+            ix => serial::PortSelection::for_port(&serial_ports[ix - 1]),
+        };
+    }
+
+    for _click in button()
+        .mid_left_of(ids.background)
+        .down(PAD * 0.5)
+        .label(if config.network_output.enabled {
+            "Broadcast - ENABLED"
+        } else {
+            "Broadcast - DISABLED"
+        })
+        .color(if config.network_output.enabled {
+            color::DARK_BLUE
+        } else {
+            color::BLACK
+        })
+        .set(ids.broadcast_toggle, ui)
+    {
+        config.network_output.enabled = !config.network_output.enabled;
+    }
+
     let frame_button_w = (COLUMN_W - PAD * 0.5) / 2.0;
     for _click in button()
         .mid_left_of(ids.background)
@@ -128,6 +287,10 @@ pub fn update(
         .set(ids.clear_frame_button, ui)
     {
         *frame = vis::Cbm8032Frame::blank_graphics();
+        vis.mark_dirty(decode::ALL_BUFFERS);
+        if let Some(handle) = serial_handle {
+            handle.request_full_frame();
+        }
     }
 
     for _click in button()
@@ -137,6 +300,10 @@ pub fn update(
         .set(ids.random_frame_button, ui)
     {
         vis::randomise_frame_data(&mut frame.data);
+        vis.mark_dirty(decode::ALL_BUFFERS);
+        if let Some(handle) = serial_handle {
+            handle.request_full_frame();
+        }
     }
 
     // Vis FPS
@@ -266,34 +433,509 @@ pub fn update(
         config.colouration.brightness = new_brightness;
     }
 
-    let label = format!("Alpha: {:.2}", config.colouration.alpha);
-    for new_alpha in slider(config.colouration.alpha, 0.0, 1.0)
-        .color(color)
+    // Phosphor
+
+    text("Phosphor")
+        .down(PAD * 1.5)
+        .font_size(16)
+        .set(ids.phosphor_text, ui);
+
+    let presets = &phosphor::Preset::ALL;
+    let preset_labels: Vec<_> = presets.iter().map(|preset| preset.name()).collect();
+    let selected = presets.iter().position(|&preset| preset == config.phosphor.preset);
+    for new_selected in drop_down_list(&preset_labels, selected)
+        .down(PAD)
+        .set(ids.phosphor_preset_list, ui)
+    {
+        config.phosphor.preset = presets[new_selected];
+    }
+
+    // Adjusting the decay of a preset switches to a custom decay starting from the preset.
+    let decay = config.phosphor.decay();
+    let label = format!("Fast Decay: {:.1}ms", decay.fast_secs * 1_000.0);
+    for new_fast_secs in slider(decay.fast_secs, 0.0, 0.25)
         .label(&label)
-        .label_color(label_color)
         .down(PAD * 0.5)
-        .set(ids.alpha_slider, ui)
+        .set(ids.phosphor_fast_slider, ui)
     {
-        config.colouration.alpha = new_alpha;
+        config.phosphor.custom = phosphor::Decay {
+            fast_secs: new_fast_secs,
+            ..decay
+        };
+        config.phosphor.preset = phosphor::Preset::Custom;
     }
 
-    let label = format!("Sustain: {:.2}", config.sustain);
-    for new_sustain in slider(config.sustain, 0.0, 1.0)
-        .color(color)
+    let label = format!("Slow Decay: {:.0}ms", decay.slow_secs * 1_000.0);
+    for new_slow_secs in slider(decay.slow_secs, 0.0, 2.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.phosphor_slow_slider, ui)
+    {
+        config.phosphor.custom = phosphor::Decay {
+            slow_secs: new_slow_secs,
+            ..decay
+        };
+        config.phosphor.preset = phosphor::Preset::Custom;
+    }
+
+    let label = format!("Slow Decay Weight: {:.2}", decay.slow_weight);
+    for new_slow_weight in slider(decay.slow_weight, 0.0, 1.0)
         .label(&label)
-        .label_color(label_color)
         .down(PAD * 0.5)
-        .set(ids.sustain_slider, ui)
+        .set(ids.phosphor_slow_weight_slider, ui)
     {
-        config.sustain = new_sustain;
+        config.phosphor.custom = phosphor::Decay {
+            slow_weight: new_slow_weight,
+            ..decay
+        };
+        config.phosphor.preset = phosphor::Preset::Custom;
     }
 
+    // CRT
+
+    text("CRT")
+        .down(PAD * 1.5)
+        .font_size(16)
+        .set(ids.crt_text, ui);
+
+    let crt = &mut config.crt;
+    for _click in button()
+        .down(PAD)
+        .label(if crt.enabled {
+            "CRT Emulation - ENABLED"
+        } else {
+            "CRT Emulation - DISABLED"
+        })
+        .color(if crt.enabled {
+            color::DARK_BLUE
+        } else {
+            color::BLACK
+        })
+        .set(ids.crt_toggle, ui)
+    {
+        crt.enabled = !crt.enabled;
+    }
+
+    let masks = [conf::CrtMask::ShadowMask, conf::CrtMask::ApertureGrille];
+    let mask_labels = ["Shadow Mask", "Aperture Grille"];
+    let selected = masks.iter().position(|&mask| mask == crt.mask);
+    for new_selected in drop_down_list(&mask_labels, selected)
+        .down(PAD * 0.5)
+        .set(ids.crt_mask_list, ui)
+    {
+        crt.mask = masks[new_selected];
+    }
+
+    let label = format!("Curvature: {:.2}", crt.curvature);
+    for new_curvature in slider(crt.curvature, 0.0, 0.5)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.crt_curvature_slider, ui)
+    {
+        crt.curvature = new_curvature;
+    }
+
+    let label = format!("Scanlines: {:.2}", crt.scanlines);
+    for new_scanlines in slider(crt.scanlines, 0.0, 1.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.crt_scanlines_slider, ui)
+    {
+        crt.scanlines = new_scanlines;
+    }
+
+    let label = format!("Mask Strength: {:.2}", crt.mask_strength);
+    for new_mask_strength in slider(crt.mask_strength, 0.0, 1.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.crt_mask_strength_slider, ui)
+    {
+        crt.mask_strength = new_mask_strength;
+    }
+
+    let label = format!("Bloom: {:.2}", crt.bloom);
+    for new_bloom in slider(crt.bloom, 0.0, 1.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.crt_bloom_slider, ui)
+    {
+        crt.bloom = new_bloom;
+    }
+
+    let label = format!("Vignette: {:.2}", crt.vignette);
+    for new_vignette in slider(crt.vignette, 0.0, 1.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.crt_vignette_slider, ui)
+    {
+        crt.vignette = new_vignette;
+    }
+
+    let label = format!("Convergence: {:.2}px", crt.convergence);
+    for new_convergence in slider(crt.convergence, 0.0, 3.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.crt_convergence_slider, ui)
+    {
+        crt.convergence = new_convergence;
+    }
+
+    // Shaders
+
+    text("Shaders")
+        .down(PAD * 1.5)
+        .font_size(16)
+        .set(ids.shader_text, ui);
+
+    let (status, status_color) = match vis.shader_error() {
+        None => {
+            let s = format!("Watching {}", vis.glsl_path().display());
+            (s, color::WHITE)
+        }
+        Some(err) => (format!("{}", err), color::LIGHT_RED),
+    };
+    widget::Text::new(&status)
+        .down(PAD)
+        .w(COLUMN_W)
+        .font_size(12)
+        .color(status_color)
+        .set(ids.shader_status_text, ui);
+
+    // Post-processing
+
+    text("Post-processing")
+        .down(PAD * 1.5)
+        .font_size(16)
+        .set(ids.post_text, ui);
+
+    let post = vis.post();
+    let status = if config.post_processing.is_empty() {
+        "No passes configured".to_string()
+    } else if post.errors().is_empty() {
+        format!("{} of {} passes enabled", post.shaders().len(), config.post_processing.len())
+    } else {
+        let errors: Vec<_> = post.errors().iter().map(|err| format!("{}", err)).collect();
+        errors.join("\n")
+    };
+    let status_color = if post.errors().is_empty() {
+        color::WHITE
+    } else {
+        color::LIGHT_RED
+    };
+    widget::Text::new(&status)
+        .down(PAD)
+        .w(COLUMN_W)
+        .font_size(12)
+        .color(status_color)
+        .set(ids.post_status_text, ui);
+
+    // A toggle for each configured pass, followed by a slider for each of its parameters.
+    let n_params = post.shaders().iter().map(|shader| shader.params.len()).sum();
+    {
+        let mut id_gen = ui.widget_id_generator();
+        ids.post_pass_toggles.resize(config.post_processing.len(), &mut id_gen);
+        ids.post_param_sliders.resize(n_params, &mut id_gen);
+    }
+    let mut slider_ids = ids.post_param_sliders.iter();
+    let passes = config.post_processing.iter_mut().zip(ids.post_pass_toggles.iter());
+    for (pass_index, (pass, &toggle_id)) in passes.enumerate() {
+        for _click in button()
+            .down(PAD * 0.5)
+            .label(&format!(
+                "{} - {}",
+                pass.shader,
+                if pass.enabled { "ENABLED" } else { "DISABLED" }
+            ))
+            .color(if pass.enabled {
+                color::DARK_BLUE
+            } else {
+                color::BLACK
+            })
+            .set(toggle_id, ui)
+        {
+            pass.enabled = !pass.enabled;
+        }
+
+        let shader = match post.shaders().iter().find(|shader| shader.pass_index == pass_index) {
+            Some(shader) if pass.enabled => shader,
+            _ => continue,
+        };
+        for (param, &slider_id) in shader.params.iter().zip(&mut slider_ids) {
+            let value = param.value(Some(pass));
+            let label = format!("{}: {:.2}", param.name, value);
+            for new_value in slider(value, param.min, param.max)
+                .label(&label)
+                .down(PAD * 0.5)
+                .set(slider_id, ui)
+            {
+                pass.params.insert(param.name.clone(), new_value);
+            }
+        }
+    }
+
+    // Replay
+
+    text("Replay")
+        .down(PAD * 1.5)
+        .font_size(16)
+        .set(ids.replay_text, ui);
+
+    let replay_button_w = (COLUMN_W - PAD * 0.5) / 2.0;
+    let paused = replay_controls
+        .map(|controls| controls.playback().paused)
+        .unwrap_or(false);
+    for _click in button()
+        .down(PAD)
+        .w(replay_button_w)
+        .label(if paused { "PLAY" } else { "PAUSE" })
+        .set(ids.replay_pause_button, ui)
+    {
+        if let Some(controls) = replay_controls {
+            let mut playback = controls.playback();
+            playback.paused = !playback.paused;
+            controls.set_playback(playback);
+        }
+    }
+
+    for _click in button()
+        .right(PAD * 0.5)
+        .w(replay_button_w)
+        .label(if config.replay.looping {
+            "LOOP - ON"
+        } else {
+            "LOOP - OFF"
+        })
+        .color(if config.replay.looping {
+            color::DARK_BLUE
+        } else {
+            color::BLACK
+        })
+        .set(ids.replay_loop_toggle, ui)
+    {
+        config.replay.looping = !config.replay.looping;
+    }
+
+    let label = format!("Speed: {:.2}x", config.replay.speed);
+    for new_speed in slider(config.replay.speed, 0.1, 4.0)
+        .label(&label)
+        .down_from(ids.replay_pause_button, PAD * 0.5)
+        .set(ids.replay_speed_slider, ui)
+    {
+        config.replay.speed = new_speed;
+    }
+
+    if let Some(controls) = replay_controls {
+        let position = controls.position().as_secs_f32();
+        let duration = controls.duration().as_secs_f32();
+        let label = format!("Position: {:.1} / {:.1} secs", position, duration);
+        for new_position in slider(position, 0.0, duration)
+            .label(&label)
+            .down(PAD * 0.5)
+            .set(ids.replay_position_slider, ui)
+        {
+            controls.seek(std::time::Duration::from_secs_f32(new_position));
+        }
+    }
+
+    // Serial line
+
+    text("Serial Line")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .set(ids.serial_line_text, ui);
+
+    let line = &mut config.serial.line;
+    let baud_labels: Vec<String> = BAUD_RATES
+        .iter()
+        .map(|baud| format!("Baud Rate - {}", baud))
+        .collect();
+    let selected = BAUD_RATES.iter().position(|&baud| baud == line.baud_rate);
+    let label = format!("Baud Rate - {}", line.baud_rate);
+    for new_selected in drop_down_list(&baud_labels, selected)
+        .down(PAD)
+        .label(&label)
+        .set(ids.baud_rate_list, ui)
+    {
+        line.baud_rate = BAUD_RATES[new_selected];
+    }
+
+    let half_w = (COLUMN_W - PAD * 0.5) / 2.0;
+    let data_bits_labels = ["5 Data Bits", "6 Data Bits", "7 Data Bits", "8 Data Bits"];
+    let selected = (5..=8).position(|bits| bits == line.data_bits);
+    for new_selected in drop_down_list(&data_bits_labels, selected)
+        .down(PAD * 0.5)
+        .w(half_w)
+        .set(ids.data_bits_list, ui)
+    {
+        line.data_bits = 5 + new_selected as u8;
+    }
+
+    let parities = [serial::Parity::None, serial::Parity::Odd, serial::Parity::Even];
+    let parity_labels = ["No Parity", "Odd Parity", "Even Parity"];
+    let selected = parities.iter().position(|&parity| parity == line.parity);
+    for new_selected in drop_down_list(&parity_labels, selected)
+        .right(PAD * 0.5)
+        .w(half_w)
+        .set(ids.parity_list, ui)
+    {
+        line.parity = parities[new_selected];
+    }
+
+    let stop_bits_labels = ["1 Stop Bit", "2 Stop Bits"];
+    let selected = (1..=2).position(|bits| bits == line.stop_bits);
+    for new_selected in drop_down_list(&stop_bits_labels, selected)
+        .down_from(ids.data_bits_list, PAD * 0.5)
+        .w(half_w)
+        .set(ids.stop_bits_list, ui)
+    {
+        line.stop_bits = 1 + new_selected as u8;
+    }
+
+    let flow_controls = [
+        serial::FlowControl::None,
+        serial::FlowControl::Software,
+        serial::FlowControl::Hardware,
+    ];
+    let flow_control_labels = ["No Flow Control", "Software Flow", "Hardware Flow"];
+    let selected = flow_controls.iter().position(|&flow| flow == line.flow_control);
+    for new_selected in drop_down_list(&flow_control_labels, selected)
+        .right(PAD * 0.5)
+        .w(half_w)
+        .set(ids.flow_control_list, ui)
+    {
+        line.flow_control = flow_controls[new_selected];
+    }
+
+    let checksums = [
+        decode::Checksum::None,
+        decode::Checksum::Crc8,
+        decode::Checksum::Crc16,
+    ];
+    let checksum_labels = ["Checksum - NONE", "Checksum - CRC-8", "Checksum - CRC-16"];
+    let selected = checksums
+        .iter()
+        .position(|&checksum| checksum == config.serial.checksum);
+    for new_selected in drop_down_list(&checksum_labels, selected)
+        .down_from(ids.stop_bits_list, PAD * 0.5)
+        .set(ids.checksum_list, ui)
+    {
+        config.serial.checksum = checksums[new_selected];
+    }
+
+    for _click in button()
+        .down(PAD * 0.5)
+        .w(half_w)
+        .label(if config.serial.partial_frames {
+            "PARTIAL - ON"
+        } else {
+            "PARTIAL - OFF"
+        })
+        .color(if config.serial.partial_frames {
+            color::DARK_BLUE
+        } else {
+            color::BLACK
+        })
+        .set(ids.partial_frames_toggle, ui)
+    {
+        config.serial.partial_frames = !config.serial.partial_frames;
+    }
+
+    for _click in button()
+        .right(PAD * 0.5)
+        .w(half_w)
+        .label(if config.show_stale_buffers {
+            "SHOW STALE - ON"
+        } else {
+            "SHOW STALE - OFF"
+        })
+        .color(if config.show_stale_buffers {
+            color::DARK_BLUE
+        } else {
+            color::BLACK
+        })
+        .set(ids.show_stale_toggle, ui)
+    {
+        config.show_stale_buffers = !config.show_stale_buffers;
+    }
+
+    let line = &mut config.serial.line;
+    let label = format!("Read Timeout: {} ms", line.read_timeout_ms);
+    for new_timeout in slider(line.read_timeout_ms as f32, 10.0, 5_000.0)
+        .label(&label)
+        .down_from(ids.partial_frames_toggle, PAD * 0.5)
+        .set(ids.read_timeout_slider, ui)
+    {
+        line.read_timeout_ms = new_timeout.round() as u64;
+    }
+
+    let label = format!("Read Buffer Size: {} bytes", line.read_buffer_size);
+    for new_size in slider(line.read_buffer_size as f32, 1.0, 4_096.0)
+        .label(&label)
+        .down(PAD * 0.5)
+        .set(ids.read_buffer_size_slider, ui)
+    {
+        line.read_buffer_size = new_size.round() as usize;
+    }
+
+    // Link health
+
+    text("Link Health")
+        .mid_left_of(ids.background)
+        .down(PAD * 1.5)
+        .font_size(16)
+        .set(ids.link_text, ui);
+
+    for _click in button()
+        .down(PAD)
+        .label("RESET LINK STATS")
+        .set(ids.link_reset_button, ui)
+    {
+        if let Some(handle) = serial_handle {
+            handle.reset_link_stats();
+        }
+    }
+
+    let stats = serial_handle.map(|handle| handle.link_stats()).unwrap_or_default();
+    let mut s = String::new();
+    s.push_str(&format!("Bytes Received:  {}\n", stats.bytes_received));
+    s.push_str(&format!("Throughput:  {:.1} KB/s\n", stats.bytes_per_sec / 1_000.0));
+    s.push_str(&format!("Frames Completed:  {}\n", stats.frames_completed));
+    s.push_str(&format!("Partial Frames:  {}\n", stats.partial_frames));
+    s.push_str(&format!("Sync Losses:  {}\n", stats.sync_losses));
+    // Only the most frequent bufnums fit within the panel.
+    const MAX_LISTED_BUFNUMS: usize = 5;
+    for (bufnum, count) in stats.sync_loss_bufnums().into_iter().take(MAX_LISTED_BUFNUMS) {
+        s.push_str(&format!("    Bufnum {}:  {}\n", bufnum, count));
+    }
+    s.push_str(&format!("Corrupted Buffers:  {}\n", stats.corrupted_buffers));
+    s.push_str(&format!("Timeouts:  {}\n", stats.timeouts));
+    s.push_str(&format!("Reconnects:  {}\n", stats.reconnects));
+    s.push_str(&format!(
+        "Read Size:  {:.1} AVG  {} MIN  {} MAX",
+        stats.read_size_avg(),
+        stats.read_size_min,
+        stats.read_size_max,
+    ));
+    widget::Text::new(&s)
+        .down(PAD)
+        .font_size(14)
+        .color(color::WHITE)
+        .set(ids.link_stats_text, ui);
+
     // Serial port info
 
     if let Some(handle) = serial_handle {
-        let info = handle.port_info();
-        let mut s = format!("USB Serial Port:  {:?}\n", info.port_name);
-        if let serialport::SerialPortType::UsbPort(ref usb) = info.port_type {
+        let port_info = handle.port_info();
+        let mut s = match port_info {
+            Some(ref info) => format!("USB Serial Port:  {:?}\n", info.port_name),
+            None => format!("Source:  {}\n", handle.description()),
+        };
+        let usb = port_info.as_ref().and_then(|info| match info.port_type {
+            serialport::SerialPortType::UsbPort(ref usb) => Some(usb),
+            _ => None,
+        });
+        if let Some(usb) = usb {
             s.push_str(&format!("    VID:  {}\n    PID:  {}\n", usb.vid, usb.pid));
             if let Some(ref serial_number) = usb.serial_number {
                 s.push_str(&format!("    Serial Number:  {}\n", serial_number));
@@ -305,6 +947,29 @@ pub fn update(
                 s.push_str(&format!("    Product:  {}\n", product));
             }
         }
+        if let Some(line) = handle.line_settings() {
+            s.push_str(&format!("    Baud Rate:  {}\n", line.baud_rate));
+        }
+        match handle.low_latency() {
+            Some(serial::LowLatency::Active) => s.push_str("    Low Latency:  ACTIVE\n"),
+            Some(serial::LowLatency::Failed(reason)) => {
+                s.push_str(&format!("    Low Latency:  FAILED ({})\n", reason));
+            }
+            Some(serial::LowLatency::Unsupported) => s.push_str("    Low Latency:  UNSUPPORTED\n"),
+            None => (),
+        }
+        if let Some(stats) = network_stats {
+            s.push_str(&format!("    Packets Received:  {}\n", stats.received()));
+            s.push_str(&format!("    Packets Dropped:  {}\n", stats.dropped()));
+            s.push_str(&format!("    Packets Discarded:  {}\n", stats.discarded()));
+        }
+        if let Some(broadcast) = handle.broadcast() {
+            s.push_str(&format!("Broadcasting To:  {}\n", broadcast));
+        }
+        if let Some(path) = handle.capture_path() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            s.push_str(&format!("Capturing To:  {}\n", name));
+        }
         widget::Text::new(&s)
             .down(PAD * 1.5)
             .font_size(14)
@@ -313,6 +978,30 @@ pub fn update(
     }
 }
 
+// A short label describing the given port for the serial port list.
+fn port_label(info: &SerialPortInfo) -> String {
+    match info.port_type {
+        serialport::SerialPortType::UsbPort(ref usb) => match usb.product {
+            Some(ref product) => format!("{} - {}", info.port_name, product),
+            None => format!("{} - USB {:04x}:{:04x}", info.port_name, usb.vid, usb.pid),
+        },
+        _ => info.port_name.clone(),
+    }
+}
+
+fn drop_down_list<T>(items: &[T], selected: Option<usize>) -> widget::DropDownList<T>
+where
+    T: AsRef<str>,
+{
+    widget::DropDownList::new(items, selected)
+        .w_h(COLUMN_W, DEFAULT_WIDGET_H)
+        .label_font_size(12)
+        .color(color::DARK_CHARCOAL)
+        .label_color(color::WHITE)
+        .border(0.0)
+        .max_visible_items(6)
+}
+
 fn text(s: &str) -> widget::Text {
     widget::Text::new(s).color(color::WHITE)
 }
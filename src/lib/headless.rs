@@ -0,0 +1,404 @@
+//! Rendering frames to image files without creating any windows.
+//!
+//! Frames may be read from a capture file, a raw dump of the screen data or a text file. Each is
+//! rendered via the `raster` module with the colouration and phosphor persistence described by
+//! the config, making it suitable for print material, thumbnails and CI.
+//!
+//! For video, `FixedRate` samples the frames at a fixed frame rate so that the output, including
+//! the decay of the phosphor, does not depend on the speed at which it is rendered.
+
+use crate::capture;
+use crate::conf::{self, Config};
+use crate::decode::{DecodeEvent, Decoder};
+use crate::raster::{self, Persistence, Rasteriser};
+use crate::vis::{self, Cbm8032Frame, Cbm8032FrameMode};
+use nannou::image;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A frame along with the time at which it was displayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimedFrame {
+    /// The time relative to the beginning of the source.
+    pub timestamp: Duration,
+    pub frame: Cbm8032Frame,
+}
+
+/// Decodes the frames within a capture file.
+///
+/// Each frame is timestamped with the chunk that completed it.
+pub struct CaptureFrames {
+    reader: capture::Reader,
+    decoder: Decoder,
+    pending: VecDeque<TimedFrame>,
+}
+
+/// Samples a sequence of timed frames at a fixed frame rate.
+///
+/// Each tick yields the most recent frame at the time of the tick, as the visualisation would
+/// display it. A blank frame is displayed until the first frame arrives. Ticks continue until the
+/// given end time has been displayed.
+pub struct FixedRate<I: Iterator> {
+    frames: Peekable<I>,
+    frame_rate: f64,
+    end: Duration,
+    tick: u64,
+    current: Cbm8032Frame,
+}
+
+/// Describes the timing of an exported image sequence.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The number of images per second.
+    pub frame_rate: f64,
+    /// The width and height of each image.
+    pub size: [u32; 2],
+    /// Every image in the sequence, in order.
+    pub frames: Vec<ManifestFrame>,
+}
+
+/// A single image within an exported sequence.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFrame {
+    /// The file name of the image, relative to the manifest.
+    pub file: String,
+    /// The time at which the image is displayed relative to the beginning of the source.
+    pub timestamp_secs: f64,
+}
+
+/// Renders a sequence of frames, tracking the persistence of the phosphor between them.
+pub struct Renderer {
+    rasteriser: Rasteriser,
+    config: Config,
+    size: [u32; 2],
+    persistence: Persistence,
+    last_timestamp: Option<Duration>,
+}
+
+impl CaptureFrames {
+    /// Open the capture at the given path, decoding it with the protocol variant and partial
+    /// recovery described by the serial config.
+    pub fn open(path: PathBuf, serial: &conf::Serial) -> io::Result<Self> {
+        let reader = capture::Reader::open(path)?;
+        let mut decoder = Decoder::with_checksum(serial.checksum);
+        decoder.set_partial_recovery(serial.partial_frames);
+        let pending = VecDeque::new();
+        Ok(CaptureFrames {
+            reader,
+            decoder,
+            pending,
+        })
+    }
+
+    /// The timestamp of the last chunk in the capture.
+    pub fn duration(&self) -> Duration {
+        self.reader.duration()
+    }
+}
+
+impl Iterator for CaptureFrames {
+    type Item = io::Result<TimedFrame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let chunk = match self.reader.next_chunk() {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+            for event in self.decoder.push(&chunk.bytes) {
+                match event {
+                    DecodeEvent::FrameComplete(frame) | DecodeEvent::PartialFrame(frame) => {
+                        let timestamp = chunk.timestamp;
+                        self.pending.push_back(TimedFrame { timestamp, frame });
+                    }
+                    _ => (),
+                }
+            }
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+impl<I> FixedRate<I>
+where
+    I: Iterator<Item = io::Result<TimedFrame>>,
+{
+    /// Sample the given frames at the given rate in Hz, up to and including the given end time.
+    pub fn new(frames: I, frame_rate: f64, end: Duration) -> Self {
+        FixedRate {
+            frames: frames.peekable(),
+            frame_rate,
+            end,
+            tick: 0,
+            current: Cbm8032Frame::blank_graphics(),
+        }
+    }
+
+    /// Skip the ticks before the given time.
+    pub fn start_at(mut self, start: Duration) -> Self {
+        self.tick = (start.as_secs_f64() * self.frame_rate).ceil() as u64;
+        self
+    }
+
+    /// The time of the given tick relative to the beginning of the source.
+    pub fn tick_timestamp(&self, tick: u64) -> Duration {
+        Duration::from_secs_f64(tick as f64 / self.frame_rate)
+    }
+}
+
+impl<I> Iterator for FixedRate<I>
+where
+    I: Iterator<Item = io::Result<TimedFrame>>,
+{
+    type Item = io::Result<TimedFrame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let timestamp = self.tick_timestamp(self.tick);
+        // Stop once the tick following the end time has been displayed.
+        if self.tick > 0 && self.tick_timestamp(self.tick - 1) >= self.end {
+            return None;
+        }
+        while let Some(res) = self.frames.peek() {
+            match res {
+                Ok(next) if next.timestamp > timestamp => break,
+                Ok(_) => {
+                    if let Some(Ok(next)) = self.frames.next() {
+                        self.current = next.frame;
+                    }
+                }
+                Err(_) => return self.frames.next(),
+            }
+        }
+        self.tick += 1;
+        let frame = self.current.clone();
+        Some(Ok(TimedFrame { timestamp, frame }))
+    }
+}
+
+impl Manifest {
+    /// An empty manifest for a sequence of images at the given rate and size.
+    pub fn new(frame_rate: f64, size: [u32; 2]) -> Self {
+        let frames = vec![];
+        Manifest {
+            frame_rate,
+            size,
+            frames,
+        }
+    }
+}
+
+impl Renderer {
+    /// Create a renderer producing images of the given size.
+    pub fn new(rasteriser: Rasteriser, config: Config, size: [u32; 2]) -> Self {
+        let persistence = Persistence::new(size);
+        Renderer {
+            rasteriser,
+            config,
+            size,
+            persistence,
+            last_timestamp: None,
+        }
+    }
+
+    /// The size of the rendered images.
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    /// The config describing the look of the rendered images.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Render the given frame as displayed at the given time.
+    ///
+    /// The phosphor decays over the time elapsed since the previously rendered frame.
+    pub fn render(&mut self, frame: &Cbm8032Frame, timestamp: Duration) -> image::RgbaImage {
+        let luminance = self.luminance(frame, timestamp);
+        raster::colourise(&luminance, &self.config.colouration)
+    }
+
+    /// Render the brightness of each pixel of the given frame as displayed at the given time,
+    /// prior to colouration.
+    pub fn luminance(&mut self, frame: &Cbm8032Frame, timestamp: Duration) -> raster::Luminance {
+        let show_stale = self.config.show_stale_buffers;
+        let luminance = self.rasteriser.luminance(frame, show_stale, self.size);
+        let elapsed = self
+            .last_timestamp
+            .map(|last| timestamp.checked_sub(last).unwrap_or_default())
+            .unwrap_or_default();
+        self.last_timestamp = Some(timestamp);
+        let decay = self.config.phosphor.decay();
+        self.persistence.update(&luminance, &decay, elapsed)
+    }
+}
+
+/// Read a frame from a raw dump of the screen data.
+///
+/// The file must contain exactly `vis::CBM_8032_FRAME_DATA_LEN` bytes.
+pub fn read_raw(path: &Path, mode: Cbm8032FrameMode) -> io::Result<Cbm8032Frame> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() != vis::CBM_8032_FRAME_DATA_LEN {
+        let desc = format!(
+            "found {} bytes but a raw frame must contain {}",
+            bytes.len(),
+            vis::CBM_8032_FRAME_DATA_LEN,
+        );
+        return Err(io::Error::new(io::ErrorKind::InvalidData, desc));
+    }
+    let mut data = Box::new([0u8; vis::CBM_8032_FRAME_DATA_LEN]);
+    data.copy_from_slice(&bytes);
+    Ok(Cbm8032Frame::new(mode, data))
+}
+
+/// Read a frame from a text file, as converted by `text_frame`.
+pub fn read_text(path: &Path, mode: Cbm8032FrameMode) -> io::Result<Cbm8032Frame> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(text_frame(&text, mode))
+}
+
+/// Create a frame displaying the given text.
+///
+/// Each line of the text fills a line of the screen. Lines beyond the width or height of the
+/// screen are truncated.
+pub fn text_frame(text: &str, mode: Cbm8032FrameMode) -> Cbm8032Frame {
+    let mut frame = Cbm8032Frame::blank_graphics();
+    frame.mode = mode;
+    let line_len = vis::CHARS_PER_LINE as usize;
+    for (line_ix, line) in text.lines().take(vis::DATA_LINES as usize).enumerate() {
+        let start = line_ix * line_len;
+        for (ix, ch) in line.bytes().take(line_len).enumerate() {
+            frame.data[start + ix] = ascii_to_screen_code(ch, mode);
+        }
+    }
+    frame
+}
+
+/// Convert the given ASCII byte to the PET screen code displaying it in the given mode.
+///
+/// In graphics mode the PET has no lower case characters, so lower case letters are displayed as
+/// upper case. Bytes without an equivalent are displayed as blanks.
+pub fn ascii_to_screen_code(byte: u8, mode: Cbm8032FrameMode) -> u8 {
+    match (byte, mode) {
+        (b' '..=b'?', _) => byte,
+        (b'@', _) | (b'[', _) | (b'\\', _) | (b']', _) | (b'^', _) => byte - b'@',
+        (b'A'..=b'Z', Cbm8032FrameMode::Graphics) => byte - b'@',
+        (b'A'..=b'Z', Cbm8032FrameMode::Text) => byte,
+        (b'a'..=b'z', _) => byte - b'`',
+        _ => Cbm8032Frame::BLANK_BYTE,
+    }
+}
+
+/// The path of the image at the given index within a numbered sequence.
+///
+/// The index is inserted before the extension of the given path, e.g. `frame.png` becomes
+/// `frame_000042.png`.
+pub fn numbered_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:06}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}_{:06}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode;
+
+    #[test]
+    fn screen_codes_match_mode() {
+        let graphics = Cbm8032FrameMode::Graphics;
+        let text = Cbm8032FrameMode::Text;
+        assert_eq!(ascii_to_screen_code(b'@', graphics), 0);
+        assert_eq!(ascii_to_screen_code(b'A', graphics), 1);
+        assert_eq!(ascii_to_screen_code(b'a', graphics), 1);
+        assert_eq!(ascii_to_screen_code(b'a', text), 1);
+        assert_eq!(ascii_to_screen_code(b'A', text), 65);
+        assert_eq!(ascii_to_screen_code(b'1', text), b'1');
+        assert_eq!(ascii_to_screen_code(b'\t', text), Cbm8032Frame::BLANK_BYTE);
+    }
+
+    #[test]
+    fn text_frame_is_truncated() {
+        let long_line = "X".repeat(100);
+        let text = vec![&long_line[..]; 30].join("\n");
+        let frame = text_frame(&text, Cbm8032FrameMode::Graphics);
+        assert!(frame.data.iter().all(|&b| b == 24));
+    }
+
+    fn timed(millis: u64, text: &str) -> io::Result<TimedFrame> {
+        let timestamp = Duration::from_millis(millis);
+        let frame = text_frame(text, Cbm8032FrameMode::Graphics);
+        Ok(TimedFrame { timestamp, frame })
+    }
+
+    #[test]
+    fn fixed_rate_displays_latest_frame() {
+        let frames = vec![timed(5, "A"), timed(10, "B"), timed(25, "C")];
+        let end = Duration::from_millis(25);
+        let ticks = FixedRate::new(frames.into_iter(), 50.0, end)
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let timestamps: Vec<_> = ticks.iter().map(|t| t.timestamp.as_millis()).collect();
+        assert_eq!(timestamps, vec![0, 20, 40]);
+        assert_eq!(ticks[0].frame, Cbm8032Frame::blank_graphics());
+        assert_eq!(ticks[1].frame, timed(10, "B").unwrap().frame);
+        assert_eq!(ticks[2].frame, timed(25, "C").unwrap().frame);
+    }
+
+    #[test]
+    fn fixed_rate_starts_at_tick() {
+        let frames = vec![timed(0, "A"), timed(30, "B")];
+        let end = Duration::from_millis(30);
+        let ticks = FixedRate::new(frames.into_iter(), 50.0, end)
+            .start_at(Duration::from_millis(30))
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(ticks.len(), 1);
+        assert_eq!(ticks[0].timestamp, Duration::from_millis(40));
+        assert_eq!(ticks[0].frame, timed(30, "B").unwrap().frame);
+    }
+
+    #[test]
+    fn numbered_paths() {
+        let path = Path::new("out/frame.png");
+        assert_eq!(numbered_path(path, 42), Path::new("out/frame_000042.png"));
+        assert_eq!(numbered_path(Path::new("frame"), 1), Path::new("frame_000001"));
+    }
+
+    // Append a chunk to the bytes of a capture file.
+    fn push_chunk(capture: &mut Vec<u8>, timestamp: Duration, bytes: &[u8]) {
+        capture.extend_from_slice(&(timestamp.as_nanos() as u64).to_le_bytes());
+        capture.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        capture.extend_from_slice(bytes);
+    }
+
+    #[test]
+    fn capture_frames_are_decoded() {
+        let first = text_frame("FIRST", Cbm8032FrameMode::Graphics);
+        let second = text_frame("SECOND", Cbm8032FrameMode::Text);
+        let mut capture = capture::MAGIC.to_vec();
+        push_chunk(&mut capture, Duration::from_millis(0), &encode::encode_frame(&first));
+        push_chunk(&mut capture, Duration::from_millis(20), &encode::encode_frame(&second));
+        let name = format!("cbm8032_headless_{}.{}", std::process::id(), capture::FILE_EXTENSION);
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, &capture).unwrap();
+        let frames = CaptureFrames::open(path.clone(), &Default::default())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].frame, first);
+        assert_eq!(frames[1].timestamp, Duration::from_millis(20));
+        assert_eq!(frames[1].frame, second);
+    }
+}
@@ -3,12 +3,28 @@ use crate::fps::Fps;
 use crate::vis::Vis;
 use nannou::prelude::*;
 use nannou::Ui;
+use serialport::SerialPortInfo;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-mod conf;
+pub mod capture;
+pub mod clip;
+pub mod conf;
+pub mod decode;
+pub mod encode;
 mod fps;
+pub mod generate;
 mod gui;
-mod serial;
-mod vis;
+pub mod headless;
+pub mod link;
+pub mod net;
+pub mod phosphor;
+pub mod post;
+pub mod raster;
+pub mod replay;
+pub mod serial;
+pub mod shader;
+pub mod vis;
 
 const WINDOW_PAD: i32 = 20;
 const GUI_WINDOW_X: i32 = WINDOW_PAD;
@@ -17,6 +33,8 @@ const VIS_WINDOW_X: i32 = GUI_WINDOW_X + gui::WINDOW_WIDTH as i32 + WINDOW_PAD;
 const VIS_WINDOW_Y: i32 = GUI_WINDOW_Y;
 const VIS_WINDOW_W: u32 = 960;
 const VIS_WINDOW_H: u32 = 540;
+const SERIAL_PORTS_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+const LINE_SETTINGS_SETTLE_DURATION: std::time::Duration = std::time::Duration::from_millis(500);
 
 struct Model {
     _vis_window: window::Id,
@@ -27,7 +45,22 @@ struct Model {
     vis: Vis,
     serial_on: bool,
     serial_handle: Option<serial::Handle>,
+    serial_source: conf::Source,
+    replay_controls: Option<Arc<replay::Controls>>,
+    network_stats: Option<Arc<net::Stats>>,
     last_serial_connection_attempt: Option<std::time::Instant>,
+    serial_ports: Vec<SerialPortInfo>,
+    last_serial_ports_refresh: Option<std::time::Instant>,
+    // The line settings as of the last update and when they were last edited, so that the stream
+    // is only restarted once they settle rather than upon every step of a slider drag.
+    last_line_settings: serial::LineSettings,
+    last_line_settings_edit: std::time::Instant,
+    captures_path: PathBuf,
+    assets_path: PathBuf,
+    // Set by the GUI to save the recently received frames to a new clip.
+    save_clip: bool,
+    // The outcome of the most recent request to save a clip, shown within the GUI.
+    clip_status: Arc<Mutex<Option<String>>>,
     vis_frame: vis::Cbm8032Frame,
     vis_fps: Fps,
 }
@@ -80,6 +113,9 @@ fn model(app: &App) -> Model {
 
     let serial_on = config.on_startup.serial;
     let serial_handle = None;
+    let serial_source = config.source;
+    let replay_controls = None;
+    let network_stats = None;
 
     let mut ui = app
         .new_ui()
@@ -94,6 +130,13 @@ fn model(app: &App) -> Model {
     let vis_frame = vis::Cbm8032Frame::blank_graphics();
     let vis_fps = Fps::default();
     let last_serial_connection_attempt = None;
+    let serial_ports = vec![];
+    let last_serial_ports_refresh = None;
+    let last_line_settings = config.serial.line.clone();
+    let last_line_settings_edit = std::time::Instant::now();
+    let captures_path = capture::directory(&assets);
+    let save_clip = false;
+    let clip_status = Arc::new(Mutex::new(None));
 
     Model {
         _vis_window: vis_window,
@@ -104,25 +147,83 @@ fn model(app: &App) -> Model {
         vis,
         serial_on,
         serial_handle,
+        serial_source,
+        replay_controls,
+        network_stats,
         last_serial_connection_attempt,
+        serial_ports,
+        last_serial_ports_refresh,
+        last_line_settings,
+        last_line_settings_edit,
+        captures_path,
+        assets_path: assets,
+        save_clip,
+        clip_status,
         vis_frame,
         vis_fps,
     }
 }
 
 fn update(_app: &App, model: &mut Model, _update: Update) {
+    // Periodically refresh the list of available serial ports for the GUI.
+    let now = std::time::Instant::now();
+    let should_refresh = match model.last_serial_ports_refresh {
+        None => true,
+        Some(last) => now.duration_since(last) > SERIAL_PORTS_REFRESH_INTERVAL,
+    };
+    if should_refresh {
+        model.last_serial_ports_refresh = Some(now);
+        match serialport::available_ports() {
+            Ok(ports) => model.serial_ports = ports,
+            Err(err) => eprintln!("failed to list available serial ports: {}", err),
+        }
+    }
+
     let ui = model.ui.set_widgets();
     let handle = model.serial_handle.as_ref();
+    let replay_controls = model.replay_controls.as_ref().map(|c| &**c);
+    let network_stats = model.network_stats.as_ref().map(|s| &**s);
+    let clip_status = model.clip_status.lock().unwrap().clone();
     gui::update(
         ui,
-        &model.ids,
+        &mut model.ids,
         &mut model.config,
         &mut model.serial_on,
+        &mut model.save_clip,
+        clip_status.as_ref().map(|s| &s[..]),
         &model.vis_fps,
         handle,
+        &model.serial_ports,
+        replay_controls,
+        network_stats,
+        &model.vis,
         &mut model.vis_frame,
     );
 
+    if model.last_line_settings != model.config.serial.line {
+        model.last_line_settings = model.config.serial.line.clone();
+        model.last_line_settings_edit = now;
+    }
+    let since_line_edit = now.duration_since(model.last_line_settings_edit);
+    let line_settled = since_line_edit > LINE_SETTINGS_SETTLE_DURATION;
+
+    // If the selected source, serial port or line settings have changed, close the stream so that
+    // it may be restarted.
+    if let Some(handle) = model.serial_handle.as_ref() {
+        let port_changed = handle
+            .port_info()
+            .map(|info| !model.config.serial.port.matches(&info))
+            .unwrap_or(false);
+        let line_changed = line_settled
+            && handle
+                .line_settings()
+                .map(|settings| *settings != model.config.serial.line)
+                .unwrap_or(false);
+        if model.serial_source != model.config.source || port_changed || line_changed {
+            model.serial_handle.take().unwrap().close();
+        }
+    }
+
     // If `serial_on` is indicated but we have no stream, start one.
     if model.serial_on && model.serial_handle.is_none() {
         let now = std::time::Instant::now();
@@ -132,9 +233,44 @@ fn update(_app: &App, model: &mut Model, _update: Update) {
         };
         if should_attempt {
             model.last_serial_connection_attempt = Some(now);
-            match serial::spawn() {
-                Ok(handle) => model.serial_handle = Some(handle),
-                Err(err) => eprintln!("failed to start serial stream: {}", err),
+            model.serial_source = model.config.source;
+            let checksum = source_checksum(&model.config);
+            let partial_recovery = model.config.serial.partial_frames;
+            match model.config.source {
+                conf::Source::Serial => {
+                    let serial = &model.config.serial;
+                    match serial::spawn(&serial.port, &serial.line, checksum, partial_recovery) {
+                        Ok(handle) => model.serial_handle = Some(handle),
+                        Err(err) => eprintln!("failed to start serial stream: {}", err),
+                    }
+                }
+                conf::Source::Replay => {
+                    match open_replay(&model.config.replay, &model.captures_path) {
+                        Ok(source) => {
+                            model.replay_controls = Some(source.controls());
+                            let handle = serial::spawn_source(source, checksum, partial_recovery);
+                            model.serial_handle = Some(handle);
+                        }
+                        Err(err) => eprintln!("failed to start replay: {}", err),
+                    }
+                }
+                conf::Source::Generator => {
+                    let generator = &model.config.generator;
+                    let source = generate::GeneratorSource::new(generator.pattern, generator.rate);
+                    let handle = serial::spawn_source(source, checksum, partial_recovery);
+                    model.serial_handle = Some(handle);
+                }
+                conf::Source::Network => {
+                    let input = &model.config.network_input;
+                    match net::NetworkSource::open(input.transport, &input.address) {
+                        Ok(source) => {
+                            model.network_stats = Some(source.stats());
+                            let handle = serial::spawn_source(source, checksum, partial_recovery);
+                            model.serial_handle = Some(handle);
+                        }
+                        Err(err) => eprintln!("failed to start network stream: {}", err),
+                    }
+                }
             }
         }
 
@@ -145,14 +281,130 @@ fn update(_app: &App, model: &mut Model, _update: Update) {
 
     // If we have a serial handle and it has closed, drop the handle.
     if model.serial_handle.as_ref().map(|h| h.is_closed()).unwrap_or(true) {
-        model.serial_handle.take();
+        // A replay closes upon reaching the end of the capture, so don't restart it.
+        if model.serial_handle.take().is_some() && model.serial_source == conf::Source::Replay {
+            model.serial_on = false;
+        }
+        model.replay_controls.take();
+        model.network_stats.take();
     }
 
+    // Keep the replay playback in sync with the config.
+    if let Some(controls) = model.replay_controls.as_ref() {
+        let mut playback = controls.playback();
+        playback.speed = model.config.replay.speed;
+        playback.looping = model.config.replay.looping;
+        if playback != controls.playback() {
+            controls.set_playback(playback);
+        }
+    }
+
+    // Save the recently received frames to a new clip, rendering on a separate thread so as not to
+    // stall the GUI.
+    if std::mem::replace(&mut model.save_clip, false) {
+        match model.serial_handle.as_ref() {
+            Some(handle) => {
+                let frames = clip::timed_frames(handle.history());
+                let config = model.config.clone();
+                let assets = model.assets_path.clone();
+                let path = clip::new_file_path(&clip::directory(&assets));
+                let clip_status = model.clip_status.clone();
+                *clip_status.lock().unwrap() = Some("Saving clip...".to_string());
+                std::thread::Builder::new()
+                    .name("clip_thread".into())
+                    .spawn(move || {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        let status = match clip::save(frames, config, &assets, &path) {
+                            Ok(_) => {
+                                println!("Saved clip to {}", path.display());
+                                format!("Saved {}", name)
+                            }
+                            Err(err) => {
+                                eprintln!("failed to save clip {}: {}", path.display(), err);
+                                format!("Failed to save clip: {}", err)
+                            }
+                        };
+                        *clip_status.lock().unwrap() = Some(status);
+                    })
+                    .expect("failed to spawn clip thread");
+            }
+            None => {
+                let status = "No frames to save - the stream is not running";
+                *model.clip_status.lock().unwrap() = Some(status.to_string());
+            }
+        }
+    }
+
+    // Start or stop capturing the raw serial stream to match the config.
     if let Some(handle) = model.serial_handle.as_ref() {
-        if let Some(new_frame) = handle.try_recv_frame() {
-            model.vis_frame = new_frame;
+        if model.config.capture.enabled && !handle.is_capture_requested() {
+            let path = capture::new_file_path(&model.captures_path);
+            handle.start_capture(path);
+        } else if !model.config.capture.enabled && handle.is_capture_requested() {
+            handle.stop_capture();
         }
     }
+
+    // Keep the checksum and partial frame recovery up to date with the config.
+    if let Some(handle) = model.serial_handle.as_ref() {
+        let checksum = source_checksum(&model.config);
+        if handle.checksum() != checksum {
+            handle.set_checksum(checksum);
+        }
+        if handle.partial_recovery() != model.config.serial.partial_frames {
+            handle.set_partial_recovery(model.config.serial.partial_frames);
+        }
+    }
+
+    // Start or stop republishing decoded frames to the network to match the config.
+    if let Some(handle) = model.serial_handle.as_ref() {
+        let output = &mut model.config.network_output;
+        if output.enabled && !handle.is_broadcasting() {
+            match net::Sink::open(output.transport, &output.address) {
+                Ok(sink) => handle.start_broadcast(sink),
+                Err(err) => {
+                    eprintln!("failed to open network output {}: {}", output.address, err);
+                    output.enabled = false;
+                }
+            }
+        } else if !output.enabled && handle.is_broadcasting() {
+            handle.stop_broadcast();
+        }
+    }
+
+    if let Some(handle) = model.serial_handle.as_ref() {
+        if let Some(delta) = handle.try_recv_delta() {
+            delta.apply(&mut model.vis_frame);
+            model.vis.mark_dirty(delta.dirty);
+        }
+    }
+}
+
+// The checksum with which frames from the configured source are decoded.
+//
+// Generated and network frames are always encoded without a checksum.
+fn source_checksum(config: &Config) -> decode::Checksum {
+    match config.source {
+        conf::Source::Serial | conf::Source::Replay => config.serial.checksum,
+        conf::Source::Generator | conf::Source::Network => decode::Checksum::None,
+    }
+}
+
+// Open the capture file selected within the replay config.
+fn open_replay(config: &conf::Replay, captures_path: &Path) -> std::io::Result<replay::ReplaySource> {
+    let path = match config.file {
+        Some(ref file) => captures_path.join(file),
+        None => capture::latest_file_path(captures_path).ok_or_else(|| {
+            let desc = format!("no captures found within {}", captures_path.display());
+            std::io::Error::new(std::io::ErrorKind::NotFound, desc)
+        })?,
+    };
+    let playback = replay::Playback {
+        speed: config.speed,
+        looping: config.looping,
+        ..Default::default()
+    };
+    replay::ReplaySource::open(path, playback)
 }
 
 fn vis_view(_app: &App, model: &Model, frame: Frame) {
@@ -0,0 +1,176 @@
+//! Health statistics for the link between the frame source and the receiving thread.
+//!
+//! These make it possible to tell a flaky cable (sync losses scattered across all buffers,
+//! reconnects) from a firmware problem (sync losses concentrated on particular buffers, a
+//! throughput far from the expected rate) at a glance.
+
+use crate::decode::MODE_BUFNUM;
+use std::time::{Duration, Instant};
+
+// The period over which throughput is measured.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Statistics describing the health of the link with a frame source.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    /// The total number of bytes read from the source.
+    pub bytes_received: u64,
+    /// The number of frames that were fully decoded.
+    pub frames_completed: u64,
+    /// The number of partially received frames published upon losing sync.
+    pub partial_frames: u64,
+    /// The number of times the decoder lost sync with the stream.
+    pub sync_losses: u64,
+    /// The number of sync losses that occurred while expecting each bufnum, indexed by bufnum.
+    pub sync_losses_by_bufnum: Vec<u64>,
+    /// The number of buffers discarded due to a checksum mismatch.
+    pub corrupted_buffers: u64,
+    /// The number of reads that timed out without receiving any data.
+    pub timeouts: u64,
+    /// The number of attempts to re-establish the source after an error.
+    pub reconnects: u64,
+    /// The number of reads that returned data.
+    pub reads: u64,
+    /// The smallest number of bytes returned by a single read.
+    pub read_size_min: usize,
+    /// The largest number of bytes returned by a single read.
+    pub read_size_max: usize,
+    /// The rate at which bytes were received over the last second.
+    pub bytes_per_sec: f64,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl Stats {
+    /// Record a read of the given number of bytes.
+    pub fn record_read(&mut self, len: usize) {
+        if self.reads == 0 {
+            self.read_size_min = len;
+            self.read_size_max = len;
+        } else {
+            self.read_size_min = std::cmp::min(self.read_size_min, len);
+            self.read_size_max = std::cmp::max(self.read_size_max, len);
+        }
+        self.reads += 1;
+        self.bytes_received += len as u64;
+        self.window_bytes += len as u64;
+        self.update_throughput();
+    }
+
+    /// Record the completion of a frame.
+    pub fn record_frame(&mut self) {
+        self.frames_completed += 1;
+    }
+
+    /// Record the publishing of a partially received frame.
+    pub fn record_partial_frame(&mut self) {
+        self.partial_frames += 1;
+    }
+
+    /// Record a loss of sync while expecting the given bufnum.
+    pub fn record_sync_loss(&mut self, bufnum: u32) {
+        self.sync_losses += 1;
+        let ix = std::cmp::min(bufnum as usize, self.sync_losses_by_bufnum.len() - 1);
+        self.sync_losses_by_bufnum[ix] += 1;
+    }
+
+    /// Record a buffer discarded due to a checksum mismatch.
+    pub fn record_corrupted_buffer(&mut self) {
+        self.corrupted_buffers += 1;
+    }
+
+    /// Record a read that timed out.
+    pub fn record_timeout(&mut self) {
+        self.timeouts += 1;
+        self.update_throughput();
+    }
+
+    /// Record an attempt to re-establish the source.
+    pub fn record_reconnect(&mut self) {
+        self.reconnects += 1;
+    }
+
+    /// The average number of bytes returned by each read.
+    pub fn read_size_avg(&self) -> f64 {
+        if self.reads == 0 {
+            return 0.0;
+        }
+        self.bytes_received as f64 / self.reads as f64
+    }
+
+    /// The bufnums at which sync was lost along with the number of losses at each, ordered from
+    /// most to least frequent.
+    pub fn sync_loss_bufnums(&self) -> Vec<(u32, u64)> {
+        let mut bufnums: Vec<_> = self
+            .sync_losses_by_bufnum
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(bufnum, &count)| (bufnum as u32, count))
+            .collect();
+        bufnums.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        bufnums
+    }
+
+    // Update the throughput once the current window has elapsed.
+    fn update_throughput(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= THROUGHPUT_WINDOW {
+            self.bytes_per_sec = self.window_bytes as f64 / elapsed.as_secs_f64();
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            bytes_received: 0,
+            frames_completed: 0,
+            partial_frames: 0,
+            sync_losses: 0,
+            sync_losses_by_bufnum: vec![0; MODE_BUFNUM as usize + 1],
+            corrupted_buffers: 0,
+            timeouts: 0,
+            reconnects: 0,
+            reads: 0,
+            read_size_min: 0,
+            read_size_max: 0,
+            bytes_per_sec: 0.0,
+            window_start: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_sizes() {
+        let mut stats = Stats::default();
+        for &len in &[64, 256, 128] {
+            stats.record_read(len);
+        }
+        assert_eq!(stats.reads, 3);
+        assert_eq!(stats.bytes_received, 448);
+        assert_eq!(stats.read_size_min, 64);
+        assert_eq!(stats.read_size_max, 256);
+        assert!((stats.read_size_avg() - 448.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sync_loss_histogram() {
+        let mut stats = Stats::default();
+        for &bufnum in &[7, 3, 7, MODE_BUFNUM, 3, 7, 1_000] {
+            stats.record_sync_loss(bufnum);
+        }
+        assert_eq!(stats.sync_losses, 7);
+        // Out of range bufnums are counted against the last bucket.
+        let expected = vec![(7, 3), (3, 2), (MODE_BUFNUM, 2)];
+        assert_eq!(stats.sync_loss_bufnums(), expected);
+    }
+}
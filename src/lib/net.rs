@@ -0,0 +1,531 @@
+//! Items related to sending and receiving decoded frames over the network.
+//!
+//! Each frame is sent as a single packet laid out as:
+//!
+//! - `PACKET_MAGIC` (4 bytes).
+//! - `u64` (little endian) sequence number, incremented by one for each frame sent.
+//! - `u64` (little endian) microseconds since the UNIX epoch at which the frame was decoded.
+//! - The mode byte, as found in the first byte of the mode buffer on the wire.
+//! - The `CBM_8032_FRAME_DATA_LEN` bytes of screen data.
+//!
+//! Over UDP each datagram contains exactly one packet. Over TCP packets are sent back to back.
+
+use crate::decode;
+use crate::encode;
+use crate::serial::FrameSource;
+use crate::vis;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Identifies a packet as containing a CBM 8032 frame.
+pub const PACKET_MAGIC: [u8; 4] = *b"CBM8";
+/// The size of the packet header preceding the screen data.
+pub const PACKET_HEADER_LEN: usize = 4 + 8 + 8 + 1;
+/// The total size of a single packet.
+pub const PACKET_LEN: usize = PACKET_HEADER_LEN + vis::CBM_8032_FRAME_DATA_LEN;
+/// The port used for network frames by default.
+pub const DEFAULT_PORT: u16 = 8032;
+
+// How long a read may wait without receiving anything before timing out, matching the serial port.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+// How long a write to a TCP subscriber may block before the subscriber is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+// The number of packets that may be queued for TCP subscribers before further packets are skipped.
+const SUBSCRIBER_QUEUE_LEN: usize = 2;
+
+/// The transport protocol over which frames are sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// A single decoded frame along with its sequence number and timestamp.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub sequence: u64,
+    /// The time at which the frame was decoded as a duration since the UNIX epoch.
+    pub timestamp: Duration,
+    pub frame: vis::Cbm8032Frame,
+}
+
+/// Tracks packet sequence numbers in order to detect dropped, duplicate and reordered packets.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceTracker {
+    last: Option<u64>,
+}
+
+/// The result of tracking a packet's sequence number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sequence {
+    /// The packet is newer than the last, with the given number of packets missing in between.
+    Accept { dropped: u64 },
+    /// The packet is a duplicate or arrived after a newer packet and should be discarded.
+    Discard,
+}
+
+/// Statistics for a network source shared with the main thread.
+#[derive(Debug, Default)]
+pub struct Stats {
+    received: AtomicU64,
+    dropped: AtomicU64,
+    discarded: AtomicU64,
+}
+
+/// A `FrameSource` receiving frames over the network.
+///
+/// Received frames are re-encoded into the serial wire format so that they pass through the same
+/// decoding, capture and delivery path as frames read from a serial port.
+pub struct NetworkSource {
+    transport: Transport,
+    address: String,
+    socket: Socket,
+    // One byte larger than a packet so that oversized datagrams are rejected rather than truncated.
+    packet: Box<[u8; PACKET_LEN + 1]>,
+    packet_len: usize,
+    sequence: SequenceTracker,
+    stats: Arc<Stats>,
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+enum Socket {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+/// Publishes decoded frames to the network.
+///
+/// Over UDP each packet is sent to the given address, which may be a multicast group. Multicast
+/// packets are not looped back to the sending host, so an instance receiving from the same group
+/// does not also receive its own frames. Over TCP the sink listens on the given address and sends
+/// each packet to all connected subscribers.
+pub struct Sink {
+    transport: Transport,
+    address: String,
+    socket: SinkSocket,
+    packet: Box<[u8; PACKET_LEN]>,
+}
+
+enum SinkSocket {
+    Udp {
+        socket: UdpSocket,
+        destination: SocketAddr,
+    },
+    // Packets are written to subscribers on a dedicated thread, so that a slow subscriber cannot
+    // stall the receiving thread.
+    Tcp {
+        packet_tx: mpsc::SyncSender<Box<[u8; PACKET_LEN]>>,
+    },
+}
+
+impl Transport {
+    /// The name of the transport protocol.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Transport::Udp => "UDP",
+            Transport::Tcp => "TCP",
+        }
+    }
+}
+
+impl Packet {
+    /// Create a packet for the given frame, timestamped with the current system time.
+    pub fn now(sequence: u64, frame: vis::Cbm8032Frame) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Packet {
+            sequence,
+            timestamp,
+            frame,
+        }
+    }
+
+    /// Write the packet to the given buffer.
+    pub fn write_to(&self, bytes: &mut [u8; PACKET_LEN]) {
+        let micros = self.timestamp.as_micros() as u64;
+        bytes[..4].copy_from_slice(&PACKET_MAGIC);
+        bytes[4..12].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[12..20].copy_from_slice(&micros.to_le_bytes());
+        bytes[20] = encode::mode_to_byte(self.frame.mode);
+        bytes[PACKET_HEADER_LEN..].copy_from_slice(&self.frame.data[..]);
+    }
+
+    /// Read a packet from the given bytes.
+    pub fn read_from(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() != PACKET_LEN || bytes[..4] != PACKET_MAGIC {
+            let desc = "not a CBM 8032 frame packet";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, desc));
+        }
+        let mut sequence = [0u8; 8];
+        let mut micros = [0u8; 8];
+        sequence.copy_from_slice(&bytes[4..12]);
+        micros.copy_from_slice(&bytes[12..20]);
+        let sequence = u64::from_le_bytes(sequence);
+        let timestamp = Duration::from_micros(u64::from_le_bytes(micros));
+        let mode = decode::byte_to_mode(bytes[20]);
+        let mut data = Box::new([0u8; vis::CBM_8032_FRAME_DATA_LEN]);
+        data.copy_from_slice(&bytes[PACKET_HEADER_LEN..]);
+        let frame = vis::Cbm8032Frame::new(mode, data);
+        Ok(Packet {
+            sequence,
+            timestamp,
+            frame,
+        })
+    }
+}
+
+impl SequenceTracker {
+    /// Track the sequence number of the next received packet.
+    ///
+    /// A sequence number of `0` is always accepted in order to handle senders restarting.
+    pub fn track(&mut self, sequence: u64) -> Sequence {
+        let dropped = match self.last {
+            Some(last) if sequence > last => sequence - last - 1,
+            Some(_) if sequence != 0 => return Sequence::Discard,
+            _ => 0,
+        };
+        self.last = Some(sequence);
+        Sequence::Accept { dropped }
+    }
+}
+
+impl Stats {
+    /// The number of packets received.
+    pub fn received(&self) -> u64 {
+        self.received.load(atomic::Ordering::Relaxed)
+    }
+
+    /// The number of packets that never arrived according to their sequence numbers.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(atomic::Ordering::Relaxed)
+    }
+
+    /// The number of duplicate or reordered packets that were discarded.
+    pub fn discarded(&self) -> u64 {
+        self.discarded.load(atomic::Ordering::Relaxed)
+    }
+}
+
+impl NetworkSource {
+    /// Begin receiving frames at the given address.
+    ///
+    /// For UDP this is the local address to bind to. If the IP is a multicast address, the socket
+    /// binds to the port on all interfaces and joins the multicast group. For TCP this is the
+    /// address of the sender to connect to.
+    pub fn open(transport: Transport, address: &str) -> io::Result<Self> {
+        let socket = open_socket(transport, address)?;
+        Ok(NetworkSource {
+            transport,
+            address: address.to_string(),
+            socket,
+            packet: Box::new([0u8; PACKET_LEN + 1]),
+            packet_len: 0,
+            sequence: SequenceTracker::default(),
+            stats: Arc::new(Stats::default()),
+            pending: Vec::with_capacity(encode::ENCODED_FRAME_LEN),
+            offset: 0,
+        })
+    }
+
+    /// Statistics that may be read from another thread once the source is spawned.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    // Receive the next whole packet.
+    fn receive_packet(&mut self) -> io::Result<Packet> {
+        match self.socket {
+            Socket::Udp(ref socket) => {
+                let len = socket.recv(&mut self.packet[..]).map_err(timeout_err)?;
+                Packet::read_from(&self.packet[..len])
+            }
+            Socket::Tcp(ref mut stream) => loop {
+                // Accumulate across reads so that a timeout mid-packet does not lose bytes.
+                while self.packet_len < PACKET_LEN {
+                    match stream.read(&mut self.packet[self.packet_len..PACKET_LEN]) {
+                        Ok(0) => {
+                            let desc = "sender closed the connection";
+                            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, desc));
+                        }
+                        Ok(len) => self.packet_len += len,
+                        Err(err) => return Err(timeout_err(err)),
+                    }
+                }
+                if self.packet[..4] == PACKET_MAGIC {
+                    self.packet_len = 0;
+                    return Packet::read_from(&self.packet[..PACKET_LEN]);
+                }
+                // Skip to the next packet rather than misreading every packet that follows.
+                self.packet_len = resync(&mut self.packet[..PACKET_LEN]);
+                let skipped = PACKET_LEN - self.packet_len;
+                eprintln!("lost sync with {}, skipped {} byte(s)", self.address, skipped);
+            },
+        }
+    }
+}
+
+impl io::Read for NetworkSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.pending.len() {
+            let packet = match self.receive_packet() {
+                Ok(packet) => packet,
+                // Skip datagrams that don't contain frames.
+                Err(ref err) if err.kind() == io::ErrorKind::InvalidData => continue,
+                Err(err) => return Err(err),
+            };
+            self.stats.received.fetch_add(1, atomic::Ordering::Relaxed);
+            match self.sequence.track(packet.sequence) {
+                Sequence::Discard => {
+                    self.stats.discarded.fetch_add(1, atomic::Ordering::Relaxed);
+                    continue;
+                }
+                Sequence::Accept { dropped } => {
+                    if dropped > 0 {
+                        eprintln!("dropped {} network frame(s) before #{}", dropped, packet.sequence);
+                        self.stats.dropped.fetch_add(dropped, atomic::Ordering::Relaxed);
+                    }
+                }
+            }
+            self.pending.clear();
+            self.offset = 0;
+            encode::encode_frame_into(&packet.frame, &mut self.pending);
+        }
+        let remaining = &self.pending[self.offset..];
+        let len = std::cmp::min(remaining.len(), buf.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+impl FrameSource for NetworkSource {
+    fn description(&self) -> String {
+        format!("{} {}", self.transport.name(), self.address)
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        std::thread::sleep(Duration::from_secs(1));
+        self.socket = open_socket(self.transport, &self.address)?;
+        self.packet_len = 0;
+        self.sequence = SequenceTracker::default();
+        Ok(())
+    }
+}
+
+impl Sink {
+    /// Open a sink publishing to the given address.
+    pub fn open(transport: Transport, address: &str) -> io::Result<Self> {
+        let addr = resolve(address)?;
+        let socket = match transport {
+            Transport::Udp => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+                socket.set_multicast_loop_v4(false)?;
+                SinkSocket::Udp {
+                    socket,
+                    destination: addr,
+                }
+            }
+            Transport::Tcp => {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                let (packet_tx, packet_rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE_LEN);
+                std::thread::Builder::new()
+                    .name("net_sink_thread".into())
+                    .spawn(move || publish_to_subscribers(listener, packet_rx))?;
+                SinkSocket::Tcp { packet_tx }
+            }
+        };
+        Ok(Sink {
+            transport,
+            address: address.to_string(),
+            socket,
+            packet: Box::new([0u8; PACKET_LEN]),
+        })
+    }
+
+    /// A short human-readable description of the sink.
+    pub fn description(&self) -> String {
+        format!("{} {}", self.transport.name(), self.address)
+    }
+
+    /// Send the packet to the destination or all subscribers.
+    ///
+    /// Packets are queued for TCP subscribers and skipped while the queue is full. Subscribers
+    /// that cannot keep up or have disconnected are dropped.
+    pub fn send(&mut self, packet: &Packet) -> io::Result<()> {
+        packet.write_to(&mut self.packet);
+        match self.socket {
+            SinkSocket::Udp {
+                ref socket,
+                destination,
+            } => {
+                socket.send_to(&self.packet[..], destination)?;
+            }
+            SinkSocket::Tcp { ref packet_tx } => match packet_tx.try_send(self.packet.clone()) {
+                Ok(()) | Err(mpsc::TrySendError::Full(_)) => (),
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    let desc = "no longer accepting subscribers";
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, desc));
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// Write each queued packet to all subscribers, accepting any pending subscribers first.
+//
+// Returns once the sink is dropped or the listener fails.
+fn publish_to_subscribers(listener: TcpListener, packet_rx: mpsc::Receiver<Box<[u8; PACKET_LEN]>>) {
+    let mut subscribers: Vec<TcpStream> = vec![];
+    for packet in packet_rx {
+        loop {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    let res = stream
+                        .set_nonblocking(false)
+                        .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+                        .and_then(|_| stream.set_nodelay(true));
+                    match res {
+                        Ok(_) => subscribers.push(stream),
+                        Err(err) => eprintln!("failed to accept subscriber {}: {}", addr, err),
+                    }
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    eprintln!("failed to accept subscribers: {}", err);
+                    return;
+                }
+            }
+        }
+        subscribers.retain(|mut stream| match stream.write_all(&packet[..]) {
+            Ok(_) => true,
+            Err(err) => {
+                let addr = stream.peer_addr().ok();
+                eprintln!("dropping subscriber {:?}: {}", addr, err);
+                false
+            }
+        });
+    }
+}
+
+fn open_socket(transport: Transport, address: &str) -> io::Result<Socket> {
+    let addr = resolve(address)?;
+    let socket = match transport {
+        Transport::Udp => {
+            let socket = match addr.ip() {
+                IpAddr::V4(group) if group.is_multicast() => {
+                    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, addr.port()))?;
+                    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+                    socket
+                }
+                _ => UdpSocket::bind(addr)?,
+            };
+            socket.set_read_timeout(Some(READ_TIMEOUT))?;
+            Socket::Udp(socket)
+        }
+        Transport::Tcp => {
+            let stream = TcpStream::connect_timeout(&addr, READ_TIMEOUT)?;
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            stream.set_nodelay(true)?;
+            Socket::Tcp(stream)
+        }
+    };
+    Ok(socket)
+}
+
+/// Resolve the given address, e.g. `"0.0.0.0:8032"` or `"visualiser.local:8032"`.
+pub fn resolve(address: &str) -> io::Result<SocketAddr> {
+    address.to_socket_addrs()?.next().ok_or_else(|| {
+        let desc = format!("could not resolve address {:?}", address);
+        io::Error::new(io::ErrorKind::InvalidInput, desc)
+    })
+}
+
+// Discard the bytes preceding the first occurrence of `PACKET_MAGIC` after the start of `bytes`,
+// moving the rest to the front. A partial magic at the end is kept in case the rest is yet to be
+// read. Returns the number of bytes kept.
+fn resync(bytes: &mut [u8]) -> usize {
+    let start = (1..bytes.len())
+        .find(|&ix| {
+            let len = std::cmp::min(bytes.len() - ix, PACKET_MAGIC.len());
+            bytes[ix..ix + len] == PACKET_MAGIC[..len]
+        })
+        .unwrap_or(bytes.len());
+    bytes.copy_within(start.., 0);
+    bytes.len() - start
+}
+
+// Socket read timeouts produce `WouldBlock` on some platforms, which the receiving thread would
+// otherwise retry indefinitely.
+fn timeout_err(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => io::Error::new(io::ErrorKind::TimedOut, err),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vis::{Cbm8032Frame, Cbm8032FrameMode, CBM_8032_FRAME_DATA_LEN};
+
+    #[test]
+    fn packet_roundtrip() {
+        let mut data = Box::new([0u8; CBM_8032_FRAME_DATA_LEN]);
+        for (ix, byte) in data.iter_mut().enumerate() {
+            *byte = ix as u8;
+        }
+        let packet = Packet {
+            sequence: 1234,
+            timestamp: Duration::from_micros(1_565_000_000_123_456),
+            frame: Cbm8032Frame::new(Cbm8032FrameMode::Text, data),
+        };
+        let mut bytes = [0u8; PACKET_LEN];
+        packet.write_to(&mut bytes);
+        assert_eq!(Packet::read_from(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn invalid_packets_are_rejected() {
+        let bytes = [0u8; PACKET_LEN];
+        assert!(Packet::read_from(&bytes).is_err());
+        let mut bytes = [0u8; PACKET_LEN];
+        bytes[..4].copy_from_slice(&PACKET_MAGIC);
+        assert!(Packet::read_from(&bytes[..PACKET_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn resync_finds_next_magic() {
+        let mut bytes = *b"xxCBM8yyCBM8zz";
+        assert_eq!(resync(&mut bytes), 12);
+        assert_eq!(&bytes[..12], b"CBM8yyCBM8zz");
+        assert_eq!(resync(&mut bytes[..12]), 6);
+        assert_eq!(&bytes[..6], b"CBM8zz");
+        // A partial magic at the end is kept.
+        let mut bytes = *b"xxxxCB";
+        assert_eq!(resync(&mut bytes), 2);
+        assert_eq!(&bytes[..2], b"CB");
+        let mut bytes = *b"xxxx";
+        assert_eq!(resync(&mut bytes), 0);
+    }
+
+    #[test]
+    fn sequence_tracking() {
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.track(5), Sequence::Accept { dropped: 0 });
+        assert_eq!(tracker.track(6), Sequence::Accept { dropped: 0 });
+        assert_eq!(tracker.track(9), Sequence::Accept { dropped: 2 });
+        assert_eq!(tracker.track(9), Sequence::Discard);
+        assert_eq!(tracker.track(7), Sequence::Discard);
+        // The sender restarted.
+        assert_eq!(tracker.track(0), Sequence::Accept { dropped: 0 });
+        assert_eq!(tracker.track(1), Sequence::Accept { dropped: 0 });
+    }
+}
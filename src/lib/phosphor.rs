@@ -0,0 +1,153 @@
+//! A time-based model of the persistence of CRT phosphors.
+//!
+//! After being struck by the beam, the brightness of a phosphor decays as the sum of a fast and a
+//! slow exponential. As each component is described by a time constant rather than a per-frame
+//! factor, the decay looks the same regardless of the rate at which the visualisation is drawn.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Well known phosphor types along with a user-defined decay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Preset {
+    /// Green, medium persistence. Used by many early monochrome terminals.
+    P1,
+    /// White, short persistence with a faint tail. Used by black and white televisions.
+    P4,
+    /// Green, short persistence with a faint tail. Common in later monochrome monitors.
+    P31,
+    /// Green, long persistence. Intended for slowly refreshed displays.
+    P39,
+    /// The decay specified within the config.
+    Custom,
+}
+
+/// The persistence of a phosphor, as the sum of two exponential decays.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Decay {
+    /// The time constant of the fast component in seconds, i.e. the time taken to fall to `1/e`.
+    pub fast_secs: f32,
+    /// The time constant of the slow component in seconds.
+    pub slow_secs: f32,
+    /// The fraction of the initial brightness contributed by the slow component.
+    pub slow_weight: f32,
+}
+
+impl Preset {
+    /// All presets, in the order in which they are presented.
+    pub const ALL: [Preset; 5] = [Preset::P1, Preset::P4, Preset::P31, Preset::P39, Preset::Custom];
+
+    /// A short name for the preset.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Preset::P1 => "P1",
+            Preset::P4 => "P4",
+            Preset::P31 => "P31",
+            Preset::P39 => "P39",
+            Preset::Custom => "Custom",
+        }
+    }
+
+    /// The decay of the phosphor, or `None` for `Custom`.
+    ///
+    /// These approximate the published times taken to fall to 10% brightness, along with a dim
+    /// tail so that short persistence phosphors still leave a faint trace.
+    pub fn decay(&self) -> Option<Decay> {
+        let decay = |fast_secs, slow_secs, slow_weight| Decay {
+            fast_secs,
+            slow_secs,
+            slow_weight,
+        };
+        match *self {
+            Preset::P1 => Some(decay(0.010, 0.060, 0.05)),
+            Preset::P4 => Some(decay(0.000_03, 0.010, 0.02)),
+            Preset::P31 => Some(decay(0.000_02, 0.020, 0.03)),
+            Preset::P39 => Some(decay(0.065, 0.400, 0.10)),
+            Preset::Custom => None,
+        }
+    }
+}
+
+impl Decay {
+    /// The brightness of the fast and slow components immediately after being struck by the beam.
+    pub fn weights(&self) -> [f32; 2] {
+        let slow = self.slow_weight.clamp(0.0, 1.0);
+        [1.0 - slow, slow]
+    }
+
+    /// The factor by which each of the fast and slow components is multiplied over the given
+    /// duration.
+    pub fn factors(&self, elapsed: Duration) -> [f32; 2] {
+        let secs = elapsed.as_secs_f32();
+        [factor(secs, self.fast_secs), factor(secs, self.slow_secs)]
+    }
+
+    /// The brightness of a phosphor the given duration after being struck by the beam.
+    pub fn brightness(&self, elapsed: Duration) -> f32 {
+        let [fast_w, slow_w] = self.weights();
+        let [fast, slow] = self.factors(elapsed);
+        fast_w * fast + slow_w * slow
+    }
+}
+
+// The factor `e^(-t/τ)`, treating a non-positive time constant as an instantaneous decay.
+fn factor(secs: f32, time_constant_secs: f32) -> f32 {
+    if time_constant_secs <= 0.0 {
+        return if secs > 0.0 { 0.0 } else { 1.0 };
+    }
+    (-secs / time_constant_secs).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_start_at_full_brightness() {
+        for preset in Preset::ALL.iter() {
+            if let Some(decay) = preset.decay() {
+                assert!((decay.brightness(Duration::from_secs(0)) - 1.0).abs() < 1e-6);
+            }
+        }
+        assert_eq!(Preset::Custom.decay(), None);
+    }
+
+    #[test]
+    fn decay_is_frame_rate_independent() {
+        let decay = Preset::P39.decay().unwrap();
+        let total = Duration::from_millis(500);
+        let expected = decay.brightness(total);
+        for &hz in &[30, 60, 144] {
+            let interval = Duration::from_secs(1) / hz;
+            let [mut fast, mut slow] = decay.weights();
+            let mut elapsed = Duration::from_secs(0);
+            while elapsed + interval <= total {
+                let [fast_f, slow_f] = decay.factors(interval);
+                fast *= fast_f;
+                slow *= slow_f;
+                elapsed += interval;
+            }
+            let [fast_f, slow_f] = decay.factors(total - elapsed);
+            let brightness = fast * fast_f + slow * slow_f;
+            assert!((brightness - expected).abs() < 1e-4, "{} Hz: {}", hz, brightness);
+        }
+    }
+
+    #[test]
+    fn long_persistence_outlasts_medium() {
+        let t = Duration::from_millis(100);
+        let p1 = Preset::P1.decay().unwrap().brightness(t);
+        let p39 = Preset::P39.decay().unwrap().brightness(t);
+        assert!(p39 > p1);
+    }
+
+    #[test]
+    fn zero_time_constant_decays_instantly() {
+        let decay = Decay {
+            fast_secs: 0.0,
+            slow_secs: 0.0,
+            slow_weight: 0.5,
+        };
+        assert_eq!(decay.brightness(Duration::from_millis(1)), 0.0);
+    }
+}
@@ -0,0 +1,597 @@
+//! A user-supplied chain of fullscreen post-processing passes applied after the main render.
+//!
+//! Each pass is a GLSL fragment shader within `assets/shaders`, listed in order within the
+//! `post_processing` section of the config. Each shader is provided with:
+//!
+//! ```glsl
+//! layout(location = 0) in vec2 v_tex_coords;
+//! layout(location = 0) out vec4 f_color;
+//!
+//! // The output of the previous pass (or of the main render for the first pass).
+//! layout(set = 0, binding = 0) uniform texture2D src;
+//! layout(set = 0, binding = 1) uniform sampler src_sampler;
+//! layout(set = 0, binding = 2) uniform Globals {
+//!     vec2 resolution;
+//!     float time;
+//! } globals;
+//! ```
+//!
+//! Shaders declaring resources at any other binding are reported as errors rather than failing to
+//! build their pipeline.
+//!
+//! A shader may also declare a block of `float` parameters at binding 3, each of which is exposed
+//! as a slider within the GUI. A trailing comment may specify the slider's minimum, maximum and
+//! default value, otherwise these are `0.0`, `1.0` and the minimum respectively.
+//!
+//! ```glsl
+//! layout(set = 0, binding = 3) uniform Params {
+//!     float strength; // 0.0 2.0 0.5
+//! } params;
+//! ```
+
+use crate::conf;
+use crate::shader::{self, CompileError};
+use nannou::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// The binding at which a shader's float parameters are declared.
+pub const PARAMS_BINDING: u32 = 3;
+
+const TEXTURE_FORMAT: wgpu::TextureFormat = Frame::TEXTURE_FORMAT;
+// Uniform buffers may not be empty, so shaders without parameters are bound a small buffer.
+const MIN_PARAMS_SIZE: u32 = 16;
+
+/// A float parameter declared by a post-processing shader.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    // The byte offset of the parameter within the uniform block.
+    offset: u32,
+}
+
+/// A compiled post-processing shader along with the parameters that it declares.
+#[derive(Clone, Debug)]
+pub struct Shader {
+    /// The name of the shader file, as listed within the config.
+    pub name: String,
+    /// The index of the pass within the config's `post_processing` passes, whose parameters are
+    /// used by this shader. The same shader may be listed more than once.
+    pub pass_index: usize,
+    pub params: Vec<Param>,
+    spirv: Vec<u8>,
+    params_size: u32,
+}
+
+/// The post-processing chain, rebuilt whenever the enabled passes or their shaders change.
+pub struct Chain {
+    shaders_path: PathBuf,
+    // The indices and names of the enabled passes when the shaders were last loaded.
+    names: Vec<(usize, String)>,
+    // The shaders of the enabled passes, in order.
+    shaders: Vec<Shader>,
+    // The errors that occurred while loading the enabled passes.
+    errors: Vec<CompileError>,
+    watcher: shader::Watcher,
+    gpu: Option<Gpu>,
+    start: Instant,
+}
+
+// The GPU resources for running the passes.
+struct Gpu {
+    size: [u32; 2],
+    msaa_samples: u32,
+    // The textures between which the passes ping-pong.
+    _textures: [wgpu::Texture; 2],
+    texture_views: [wgpu::TextureView; 2],
+    globals_buffer: wgpu::Buffer,
+    passes: Vec<Pass>,
+    _sampler: wgpu::Sampler,
+}
+
+struct Pass {
+    pipeline: wgpu::RenderPipeline,
+    // The bind group reading from each of the two textures.
+    bind_groups: [wgpu::BindGroup; 2],
+    params_buffer: wgpu::Buffer,
+    // The parameter data most recently written to the params buffer.
+    params_bytes: Vec<u8>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Globals {
+    resolution: [f32; 2],
+    time: f32,
+    _pad: f32,
+}
+
+impl Chain {
+    /// Create an empty chain that loads shaders from the given directory.
+    pub fn new(shaders_path: PathBuf) -> Self {
+        Chain {
+            shaders_path,
+            names: vec![],
+            shaders: vec![],
+            errors: vec![],
+            watcher: shader::Watcher::new(vec![]),
+            gpu: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Whether or not there are any passes to run.
+    pub fn is_active(&self) -> bool {
+        !self.shaders.is_empty()
+    }
+
+    /// The shaders of the enabled passes, in order.
+    pub fn shaders(&self) -> &[Shader] {
+        &self.shaders
+    }
+
+    /// The errors produced by the most recent attempt to load the enabled passes.
+    pub fn errors(&self) -> &[CompileError] {
+        &self.errors
+    }
+
+    /// Reload the shaders if the enabled passes changed or any of their shaders were modified.
+    ///
+    /// A shader that fails to compile is replaced by its previously compiled version, or skipped
+    /// if there is none.
+    pub fn update_shaders(&mut self, passes: &[conf::PostPass]) {
+        let names: Vec<(usize, &str)> = passes
+            .iter()
+            .enumerate()
+            .filter(|(_, pass)| pass.enabled)
+            .map(|(ix, pass)| (ix, &pass.shader[..]))
+            .collect();
+        let loaded = self
+            .names
+            .iter()
+            .map(|(ix, name)| (*ix, &name[..]))
+            .eq(names.iter().cloned());
+        let modified = self.watcher.poll();
+        if loaded && !modified {
+            return;
+        }
+
+        let mut shaders = vec![];
+        let mut errors = vec![];
+        for &(pass_index, name) in &names {
+            match load(&self.shaders_path.join(name)) {
+                Ok(mut shader) => {
+                    shader.name = name.to_string();
+                    shader.pass_index = pass_index;
+                    shaders.push(shader);
+                }
+                Err(err) => {
+                    eprintln!("failed to load post-processing shader: {}", err);
+                    errors.push(err);
+                    let previous = self.shaders.iter().find(|shader| shader.name == name);
+                    let previous = previous.cloned().map(|shader| Shader {
+                        pass_index,
+                        ..shader
+                    });
+                    shaders.extend(previous);
+                }
+            }
+        }
+        if !loaded {
+            let paths = names.iter().map(|&(_, name)| self.shaders_path.join(name)).collect();
+            self.watcher = shader::Watcher::new(paths);
+            self.names = names.iter().map(|&(ix, name)| (ix, name.to_string())).collect();
+        }
+        self.shaders = shaders;
+        self.errors = errors;
+        self.gpu = None;
+    }
+
+    /// The texture view to which the main render should be written when the chain is active.
+    ///
+    /// Returns `None` until `prepare` has created the GPU resources.
+    pub fn input(&self) -> Option<&wgpu::TextureView> {
+        self.gpu.as_ref().map(|gpu| &gpu.texture_views[0])
+    }
+
+    /// Prepare the GPU resources for the given frame size and sample count, rebuilding them if
+    /// necessary.
+    pub fn prepare(&mut self, device: &wgpu::Device, size: [u32; 2], msaa_samples: u32) {
+        if !self.is_active() {
+            self.gpu = None;
+            return;
+        }
+        let up_to_date = self
+            .gpu
+            .as_ref()
+            .map(|gpu| gpu.size == size && gpu.msaa_samples == msaa_samples)
+            .unwrap_or(false);
+        if !up_to_date {
+            self.gpu = Some(init_gpu(device, size, msaa_samples, &self.shaders));
+        }
+    }
+
+    /// Encode each of the passes, reading the main render from `input` and writing the result of
+    /// the final pass to `output`.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        passes: &[conf::PostPass],
+        output: &wgpu::TextureView,
+    ) {
+        let gpu = match self.gpu.as_mut() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+
+        // Update the globals.
+        let [w, h] = gpu.size;
+        let globals = Globals {
+            resolution: [w as f32, h as f32],
+            time: self.start.elapsed().as_secs_f32(),
+            _pad: 0.0,
+        };
+        let globals_size = std::mem::size_of::<Globals>() as wgpu::BufferAddress;
+        let usage = wgpu::BufferUsage::COPY_SRC;
+        let new_globals_buffer = device.create_buffer_with_data(globals_as_bytes(&globals), usage);
+        encoder.copy_buffer_to_buffer(&new_globals_buffer, 0, &gpu.globals_buffer, 0, globals_size);
+
+        let n_passes = gpu.passes.len();
+        for (i, (pass, shader)) in gpu.passes.iter_mut().zip(&self.shaders).enumerate() {
+            // Update the parameters if they changed.
+            let config = passes.get(shader.pass_index);
+            let params_bytes = params_bytes(shader, config);
+            if params_bytes != pass.params_bytes {
+                let new_params_buffer = device.create_buffer_with_data(&params_bytes, usage);
+                let size = params_bytes.len() as wgpu::BufferAddress;
+                encoder.copy_buffer_to_buffer(&new_params_buffer, 0, &pass.params_buffer, 0, size);
+                pass.params_bytes = params_bytes;
+            }
+
+            // Read from the texture written by the previous pass.
+            let dst = if i + 1 == n_passes {
+                output
+            } else {
+                &gpu.texture_views[(i + 1) % 2]
+            };
+            let mut render_pass = wgpu::RenderPassBuilder::new()
+                .color_attachment(dst, |color| color)
+                .begin(encoder);
+            render_pass.set_bind_group(0, &pass.bind_groups[i % 2], &[]);
+            render_pass.set_pipeline(&pass.pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+impl Param {
+    /// The value of the parameter within the given pass config, or its default.
+    pub fn value(&self, pass: Option<&conf::PostPass>) -> f32 {
+        pass.and_then(|pass| pass.params.get(&self.name))
+            .cloned()
+            .unwrap_or(self.default)
+    }
+}
+
+/// The directory in which post-processing shaders are stored.
+pub fn shaders_path(assets: &Path) -> PathBuf {
+    assets.join("shaders")
+}
+
+/// Load and compile the post-processing shader at the given path.
+pub fn load(path: &Path) -> Result<Shader, CompileError> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let error = |message: String| CompileError {
+        name: name.clone(),
+        message,
+    };
+    let source = std::fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+    let module = shader::parse(&source, shader::Stage::Fragment).map_err(error)?;
+    check_bindings(&module).map_err(error)?;
+    let (params, params_size) = reflect_params(&module, &source).map_err(error)?;
+    let spirv = shader::write_spirv(&module).map_err(error)?;
+    Ok(Shader {
+        name: name.clone(),
+        pass_index: 0,
+        params,
+        spirv,
+        params_size,
+    })
+}
+
+// Check that every resource declared by the shader matches the layout provided to each pass.
+fn check_bindings(module: &naga::Module) -> Result<(), String> {
+    for (_, var) in module.global_variables.iter() {
+        let binding = match var.binding {
+            Some(ref binding) => binding,
+            None => continue,
+        };
+        let name = var.name.as_ref().map(|name| &name[..]).unwrap_or("resource");
+        if binding.group != 0 || binding.binding > PARAMS_BINDING {
+            return Err(format!(
+                "`{}` is declared at set {} binding {}, but only bindings 0 to {} of set 0 are \
+                 provided",
+                name, binding.group, binding.binding, PARAMS_BINDING,
+            ));
+        }
+        let (expected, matches) = match (binding.binding, &module.types[var.ty].inner) {
+            (0, naga::TypeInner::Image { dim, arrayed, class }) => {
+                let sampled = match class {
+                    naga::ImageClass::Sampled { multi, .. } => !multi,
+                    _ => false,
+                };
+                ("texture2D", *dim == naga::ImageDimension::D2 && !arrayed && sampled)
+            }
+            (0, _) => ("texture2D", false),
+            (1, naga::TypeInner::Sampler { comparison }) => ("sampler", !comparison),
+            (1, _) => ("sampler", false),
+            (_, naga::TypeInner::Struct { .. }) => {
+                ("uniform block", var.space == naga::AddressSpace::Uniform)
+            }
+            (_, _) => ("uniform block", false),
+        };
+        if !matches {
+            let desc = format!("`{}` at binding {} must be a {}", name, binding.binding, expected);
+            return Err(desc);
+        }
+    }
+    Ok(())
+}
+
+// Find the float parameters declared at `PARAMS_BINDING` along with the size of the block.
+fn reflect_params(module: &naga::Module, source: &str) -> Result<(Vec<Param>, u32), String> {
+    let binding = naga::ResourceBinding {
+        group: 0,
+        binding: PARAMS_BINDING,
+    };
+    let var = module
+        .global_variables
+        .iter()
+        .map(|(_, var)| var)
+        .find(|var| var.binding.as_ref() == Some(&binding));
+    let var = match var {
+        None => return Ok((vec![], 0)),
+        Some(var) => var,
+    };
+    let (members, span) = match module.types[var.ty].inner {
+        naga::TypeInner::Struct { ref members, span } => (members, span),
+        _ => return Err(format!("binding {} must be a uniform block", PARAMS_BINDING)),
+    };
+    let mut params = vec![];
+    for member in members {
+        let name = member.name.clone().unwrap_or_default();
+        match module.types[member.ty].inner {
+            naga::TypeInner::Scalar {
+                kind: naga::ScalarKind::Float,
+                width: 4,
+            } => (),
+            _ => return Err(format!("parameter `{}` must be a `float`", name)),
+        }
+        let (min, max, default) = param_range(source, &name);
+        params.push(Param {
+            name,
+            min,
+            max,
+            default,
+            offset: member.offset,
+        });
+    }
+    Ok((params, span))
+}
+
+// Parse the optional `// min max default` comment following a parameter's declaration.
+fn param_range(source: &str, name: &str) -> (f32, f32, f32) {
+    let decl = format!("float {};", name);
+    let numbers: Vec<f32> = source
+        .lines()
+        .find_map(|line| {
+            let mut split = line.splitn(2, "//");
+            let code = split.next().unwrap_or("");
+            let code: Vec<_> = code.split_whitespace().collect();
+            if code.join(" ") != decl {
+                return None;
+            }
+            split.next()
+        })
+        .map(|comment| comment.split_whitespace().filter_map(|s| s.parse().ok()).collect())
+        .unwrap_or_default();
+    let min = numbers.first().cloned().unwrap_or(0.0);
+    let max = numbers.get(1).cloned().unwrap_or(1.0);
+    let default = numbers.get(2).cloned().unwrap_or(min);
+    (min, max, default)
+}
+
+// The contents of the params buffer for the given shader and pass config.
+fn params_bytes(shader: &Shader, pass: Option<&conf::PostPass>) -> Vec<u8> {
+    let size = std::cmp::max(shader.params_size, MIN_PARAMS_SIZE);
+    let mut bytes = vec![0u8; size as usize];
+    for param in &shader.params {
+        let start = param.offset as usize;
+        let value = param.value(pass).to_ne_bytes();
+        bytes[start..start + value.len()].copy_from_slice(&value);
+    }
+    bytes
+}
+
+fn init_gpu(device: &wgpu::Device, size: [u32; 2], msaa_samples: u32, shaders: &[Shader]) -> Gpu {
+    let vs_mod = wgpu::shader_from_spirv_bytes(device, include_bytes!("glsl/post_vert.spv"));
+    let create_texture = || {
+        wgpu::TextureBuilder::new()
+            .size(size)
+            .usage(wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED)
+            .format(TEXTURE_FORMAT)
+            .build(device)
+    };
+    let textures = [create_texture(), create_texture()];
+    let texture_views = [textures[0].view().build(), textures[1].view().build()];
+    let sampler = wgpu::SamplerBuilder::new()
+        .mag_filter(wgpu::FilterMode::Linear)
+        .min_filter(wgpu::FilterMode::Linear)
+        .build(device);
+
+    let globals = Globals {
+        resolution: [0.0; 2],
+        time: 0.0,
+        _pad: 0.0,
+    };
+    let usage = wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST;
+    let globals_buffer = device.create_buffer_with_data(globals_as_bytes(&globals), usage);
+
+    let bind_group_layout = wgpu::BindGroupLayoutBuilder::new()
+        .sampled_texture(
+            wgpu::ShaderStage::FRAGMENT,
+            false,
+            wgpu::TextureViewDimension::D2,
+            textures[0].component_type(),
+        )
+        .sampler(wgpu::ShaderStage::FRAGMENT)
+        .uniform_buffer(wgpu::ShaderStage::FRAGMENT, false)
+        .uniform_buffer(wgpu::ShaderStage::FRAGMENT, false)
+        .build(device);
+    let desc = wgpu::PipelineLayoutDescriptor {
+        bind_group_layouts: &[&bind_group_layout],
+    };
+    let pipeline_layout = device.create_pipeline_layout(&desc);
+
+    let passes = shaders
+        .iter()
+        .enumerate()
+        .map(|(i, shader)| {
+            // Only the final pass writes to the frame's multisampled texture.
+            let sample_count = if i + 1 == shaders.len() { msaa_samples } else { 1 };
+            let fs_mod = wgpu::shader_from_spirv_bytes(device, &shader.spirv);
+            let pipeline = wgpu::RenderPipelineBuilder::from_layout(&pipeline_layout, &vs_mod)
+                .fragment_shader(&fs_mod)
+                .color_format(TEXTURE_FORMAT)
+                .sample_count(sample_count)
+                .build(device);
+            let params_bytes = params_bytes(shader, None);
+            let params_buffer = device.create_buffer_with_data(&params_bytes, usage);
+            let params_size = params_bytes.len() as wgpu::BufferAddress;
+            let create_bind_group = |src: &wgpu::TextureView| {
+                wgpu::BindGroupBuilder::new()
+                    .texture_view(src)
+                    .sampler(&sampler)
+                    .buffer::<Globals>(&globals_buffer, 0..1)
+                    .buffer_bytes(&params_buffer, 0..params_size)
+                    .build(device, &bind_group_layout)
+            };
+            let bind_groups = [
+                create_bind_group(&texture_views[0]),
+                create_bind_group(&texture_views[1]),
+            ];
+            Pass {
+                pipeline,
+                bind_groups,
+                params_buffer,
+                params_bytes,
+            }
+        })
+        .collect();
+
+    Gpu {
+        size,
+        msaa_samples,
+        _textures: textures,
+        texture_views,
+        globals_buffer,
+        passes,
+        _sampler: sampler,
+    }
+}
+
+fn globals_as_bytes(globals: &Globals) -> &[u8] {
+    unsafe { wgpu::bytes::from(globals) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "#version 450
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 0) out vec4 f_color;
+layout(set = 0, binding = 0) uniform texture2D src;
+layout(set = 0, binding = 1) uniform sampler src_sampler;
+layout(set = 0, binding = 3) uniform Params {
+    float strength; // 0.0 2.0 0.5
+    float   radius;
+} params;
+void main() {
+    vec4 c = texture(sampler2D(src, src_sampler), v_tex_coords);
+    f_color = c * params.strength + vec4(params.radius);
+}
+";
+
+    #[test]
+    fn params_are_reflected() {
+        let module = shader::parse(SOURCE, shader::Stage::Fragment).unwrap();
+        let (params, size) = reflect_params(&module, SOURCE).unwrap();
+        let names: Vec<_> = params.iter().map(|p| &p.name[..]).collect();
+        assert_eq!(names, vec!["strength", "radius"]);
+        assert_eq!((params[0].min, params[0].max, params[0].default), (0.0, 2.0, 0.5));
+        assert_eq!((params[1].min, params[1].max, params[1].default), (0.0, 1.0, 0.0));
+        assert_eq!((params[0].offset, params[1].offset), (0, 4));
+        assert!(size >= 8);
+    }
+
+    #[test]
+    fn params_bytes_use_config_values() {
+        let module = shader::parse(SOURCE, shader::Stage::Fragment).unwrap();
+        let (params, params_size) = reflect_params(&module, SOURCE).unwrap();
+        let shader = Shader {
+            name: "test.frag".to_string(),
+            pass_index: 0,
+            params,
+            spirv: vec![],
+            params_size,
+        };
+        let mut pass = conf::PostPass {
+            shader: shader.name.clone(),
+            enabled: true,
+            params: Default::default(),
+        };
+        pass.params.insert("radius".to_string(), 3.0);
+        let bytes = params_bytes(&shader, Some(&pass));
+        assert_eq!(&bytes[0..4], &0.5f32.to_ne_bytes());
+        assert_eq!(&bytes[4..8], &3.0f32.to_ne_bytes());
+    }
+
+    #[test]
+    fn non_float_params_are_rejected() {
+        let source = SOURCE.replace("float   radius;", "vec2 radius;").replace(
+            "vec4(params.radius)",
+            "vec4(params.radius, 0.0, 0.0)",
+        );
+        let module = shader::parse(&source, shader::Stage::Fragment).unwrap();
+        assert!(reflect_params(&module, &source).is_err());
+    }
+
+    #[test]
+    fn unprovided_bindings_are_rejected() {
+        let module = shader::parse(SOURCE, shader::Stage::Fragment).unwrap();
+        assert!(check_bindings(&module).is_ok());
+        let source = SOURCE.replace("binding = 3", "binding = 4");
+        let module = shader::parse(&source, shader::Stage::Fragment).unwrap();
+        assert!(check_bindings(&module).is_err());
+        let source = SOURCE.replace("binding = 1", "binding = 2");
+        let module = shader::parse(&source, shader::Stage::Fragment).unwrap();
+        assert!(check_bindings(&module).is_err());
+    }
+
+    #[test]
+    fn bundled_shaders_load() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        for entry in std::fs::read_dir(shaders_path(&assets)).unwrap() {
+            let path = entry.unwrap().path();
+            load(&path).unwrap();
+        }
+    }
+}
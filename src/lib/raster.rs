@@ -0,0 +1,269 @@
+//! A software reference renderer for `Cbm8032Frame`s.
+//!
+//! Produces the same image as the main pass of `vis::view` on the CPU, allowing for rendering on
+//! machines without a GPU and for verifying the glyph placement, mode row offsets and colouration.
+//! The CRT emulation mode and post-processing passes are not applied.
+//!
+//! The persistence of the phosphor across a sequence of frames may be emulated via `Persistence`.
+
+use crate::conf;
+use crate::phosphor;
+use crate::vis::{self, Cbm8032Frame};
+use nannou::image::{self, GenericImageView};
+use nannou::prelude::*;
+use std::path::Path;
+use std::time::Duration;
+
+/// The brightness of each pixel of a rendered frame, prior to colouration.
+pub type Luminance = image::ImageBuffer<image::Luma<f32>, Vec<f32>>;
+
+/// Renders frames using the character sheet.
+pub struct Rasteriser {
+    // The red channel of the character sheet, as sampled by the shaders.
+    char_sheet: image::GrayImage,
+}
+
+impl Rasteriser {
+    /// Create a rasteriser using the given character sheet.
+    pub fn new(char_sheet: &image::DynamicImage) -> Self {
+        let (w, h) = char_sheet.dimensions();
+        let char_sheet = image::GrayImage::from_fn(w, h, |x, y| {
+            image::Luma([char_sheet.get_pixel(x, y)[0]])
+        });
+        Rasteriser { char_sheet }
+    }
+
+    /// Load the character sheet from the given assets directory.
+    pub fn open(assets_path: &Path) -> image::ImageResult<Self> {
+        let char_sheet = image::open(vis::char_sheet_path(assets_path))?;
+        Ok(Self::new(&char_sheet))
+    }
+
+    /// The size at which each pixel of the character sheet maps to a single pixel of the image.
+    pub fn native_size(&self) -> [u32; 2] {
+        let (w, h) = self.char_sheet.dimensions();
+        let cell_w = w / vis::CHAR_SHEET_COLS as u32;
+        let cell_h = h / vis::CHAR_SHEET_ROWS as u32;
+        [cell_w * vis::CHARS_PER_LINE as u32, cell_h * vis::TOTAL_LINES as u32]
+    }
+
+    /// Render the brightness of each pixel of the frame at the given size.
+    ///
+    /// Like the shaders, each pixel samples the nearest texel of the character sheet. If
+    /// `show_stale` is `true`, stale characters are shown in reverse video.
+    pub fn luminance(
+        &self,
+        frame: &Cbm8032Frame,
+        show_stale: bool,
+        [w, h]: [u32; 2],
+    ) -> Luminance {
+        let (sheet_w, sheet_h) = self.char_sheet.dimensions();
+        let cols = vis::CHARS_PER_LINE as f32;
+        let rows = vis::TOTAL_LINES as f32;
+        Luminance::from_fn(w, h, |x, y| {
+            // The position of the pixel's centre in characters.
+            let cx = (x as f32 + 0.5) * cols / w as f32;
+            let cy = (y as f32 + 0.5) * rows / h as f32;
+            let char_index = cy as usize * vis::CHARS_PER_LINE as usize + cx as usize;
+            let byte = screen_byte(frame, char_index, show_stale);
+            let [col, row] = vis::byte_to_char_sheet_col_row(byte, &frame.mode);
+            let [u, v] = vis::char_sheet_col_row_to_tex_coords_offset([col, row]);
+            let u = u + cx.fract() / vis::CHAR_SHEET_COLS as f32;
+            let v = v + cy.fract() / vis::CHAR_SHEET_ROWS as f32;
+            let sx = ((u * sheet_w as f32) as u32).min(sheet_w - 1);
+            let sy = ((v * sheet_h as f32) as u32).min(sheet_h - 1);
+            image::Luma([self.char_sheet[(sx, sy)][0] as f32 / 255.0])
+        })
+    }
+
+    /// Render the frame with the given config's colouration at the given size.
+    pub fn render(
+        &self,
+        frame: &Cbm8032Frame,
+        config: &conf::Config,
+        size: [u32; 2],
+    ) -> image::RgbaImage {
+        let luminance = self.luminance(frame, config.show_stale_buffers, size);
+        colourise(&luminance, &config.colouration)
+    }
+}
+
+/// The brightness of the fast and slow components of the phosphor at each pixel.
+///
+/// Mirrors the decay image of the visualisation.
+pub struct Persistence {
+    fast: Luminance,
+    slow: Luminance,
+}
+
+impl Persistence {
+    /// Phosphor that has not yet been struck by the beam, at the given size.
+    pub fn new([w, h]: [u32; 2]) -> Self {
+        Persistence {
+            fast: Luminance::new(w, h),
+            slow: Luminance::new(w, h),
+        }
+    }
+
+    /// Decay the phosphor over the elapsed duration, then excite it with the given luminance.
+    ///
+    /// Returns the brightness of each pixel as it would be displayed, i.e. the brighter of the
+    /// luminance and what remains of the decay.
+    pub fn update(
+        &mut self,
+        luminance: &Luminance,
+        decay: &phosphor::Decay,
+        elapsed: Duration,
+    ) -> Luminance {
+        assert_eq!(luminance.dimensions(), self.fast.dimensions());
+        let [fast_f, slow_f] = decay.factors(elapsed);
+        let [fast_w, slow_w] = decay.weights();
+        let (w, h) = luminance.dimensions();
+        Luminance::from_fn(w, h, |x, y| {
+            let l = luminance[(x, y)][0];
+            let fast = &mut self.fast[(x, y)][0];
+            *fast = (*fast * fast_f).max(fast_w * l);
+            let slow = &mut self.slow[(x, y)][0];
+            *slow = (*slow * slow_f).max(slow_w * l);
+            image::Luma([l.max(self.fast[(x, y)][0] + self.slow[(x, y)][0])])
+        })
+    }
+}
+
+/// Apply the colouration to the given luminance, producing an sRGB image.
+pub fn colourise(luminance: &Luminance, colouration: &conf::Colouration) -> image::RgbaImage {
+    let lin_srgb: LinSrgb = colouration.hsv().into();
+    let (w, h) = luminance.dimensions();
+    image::RgbaImage::from_fn(w, h, |x, y| {
+        let l = luminance[(x, y)][0];
+        let lin = LinSrgb::new(lin_srgb.red * l, lin_srgb.green * l, lin_srgb.blue * l);
+        let srgb = Srgb::from_linear(lin);
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        image::Rgba([to_u8(srgb.red), to_u8(srgb.green), to_u8(srgb.blue), 255])
+    })
+}
+
+// The byte for the character at the given index, where the index includes the leading blank line.
+//
+// Mirrors `screen_byte` within `shader.vert` along with the reverse video applied to stale
+// buffers when writing the screen buffer.
+fn screen_byte(frame: &Cbm8032Frame, char_index: usize, show_stale: bool) -> u8 {
+    let first_data_char = (vis::BLANK_LINES / 2) as usize * vis::CHARS_PER_LINE as usize;
+    let data_index = match char_index.checked_sub(first_data_char) {
+        Some(ix) if ix < vis::CBM_8032_FRAME_DATA_LEN => ix,
+        _ => return Cbm8032Frame::BLANK_BYTE,
+    };
+    let byte = frame.data[data_index];
+    if show_stale && frame.is_stale(data_index) {
+        byte ^ 0x80
+    } else {
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vis::Cbm8032FrameMode;
+
+    fn rasteriser() -> Rasteriser {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        Rasteriser::open(&assets).unwrap()
+    }
+
+    // The luminance of the character sheet cell at the given column and row.
+    fn sheet_cell(r: &Rasteriser, [col, row]: [u8; 2]) -> Vec<f32> {
+        let (w, h) = r.char_sheet.dimensions();
+        let cell_w = w / vis::CHAR_SHEET_COLS as u32;
+        let cell_h = h / vis::CHAR_SHEET_ROWS as u32;
+        let mut cell = vec![];
+        for y in 0..cell_h {
+            for x in 0..cell_w {
+                let p = r.char_sheet[(col as u32 * cell_w + x, row as u32 * cell_h + y)];
+                cell.push(p[0] as f32 / 255.0);
+            }
+        }
+        cell
+    }
+
+    // The luminance of the screen cell at the given column and row, including blank lines.
+    fn screen_cell(r: &Rasteriser, luminance: &Luminance, [col, row]: [u32; 2]) -> Vec<f32> {
+        let [w, h] = r.native_size();
+        let cell_w = w / vis::CHARS_PER_LINE as u32;
+        let cell_h = h / vis::TOTAL_LINES as u32;
+        let mut cell = vec![];
+        for y in 0..cell_h {
+            for x in 0..cell_w {
+                cell.push(luminance[(col * cell_w + x, row * cell_h + y)][0]);
+            }
+        }
+        cell
+    }
+
+    #[test]
+    fn blank_frame_is_dark() {
+        let r = rasteriser();
+        let frame = Cbm8032Frame::blank_graphics();
+        let luminance = r.luminance(&frame, false, r.native_size());
+        assert!(luminance.pixels().all(|p| p[0] == 0.0));
+    }
+
+    #[test]
+    fn glyphs_match_char_sheet() {
+        let r = rasteriser();
+        for &mode in &[Cbm8032FrameMode::Graphics, Cbm8032FrameMode::Text] {
+            let mut frame = Cbm8032Frame::blank_graphics();
+            frame.mode = mode;
+            // Place bytes in the first and last character of the data.
+            frame.data[0] = 1;
+            frame.data[vis::CBM_8032_FRAME_DATA_LEN - 1] = 0xA0;
+            let luminance = r.luminance(&frame, false, r.native_size());
+            let first = screen_cell(&r, &luminance, [0, 1]);
+            let last_row = vis::DATA_LINES as u32;
+            let last = screen_cell(&r, &luminance, [vis::CHARS_PER_LINE as u32 - 1, last_row]);
+            assert_eq!(first, sheet_cell(&r, vis::byte_to_char_sheet_col_row(1, &mode)));
+            assert_eq!(last, sheet_cell(&r, vis::byte_to_char_sheet_col_row(0xA0, &mode)));
+        }
+    }
+
+    #[test]
+    fn stale_buffers_are_reversed() {
+        let r = rasteriser();
+        let mut frame = Cbm8032Frame::blank_graphics();
+        frame.stale = 1;
+        let size = r.native_size();
+        assert!(r.luminance(&frame, false, size).pixels().all(|p| p[0] == 0.0));
+        let luminance = r.luminance(&frame, true, size);
+        let reversed = sheet_cell(&r, vis::byte_to_char_sheet_col_row(0xA0, &frame.mode));
+        assert_eq!(screen_cell(&r, &luminance, [0, 1]), reversed);
+        assert_eq!(screen_cell(&r, &luminance, [40, 1]), screen_cell(&r, &luminance, [0, 0]));
+    }
+
+    #[test]
+    fn colourise_matches_colouration() {
+        let colouration = conf::Colouration::default();
+        let mut luminance = Luminance::new(2, 1);
+        luminance[(1, 0)] = image::Luma([1.0]);
+        let image = colourise(&luminance, &colouration);
+        assert_eq!(image[(0, 0)], image::Rgba([0, 0, 0, 255]));
+        let lin_srgb: LinSrgb = colouration.hsv().into();
+        let srgb = Srgb::from_linear(lin_srgb);
+        let g = (srgb.green * 255.0).round() as u8;
+        assert_eq!(image[(1, 0)][1], g);
+    }
+
+    #[test]
+    fn persistence_follows_decay() {
+        let decay = phosphor::Preset::P39.decay().unwrap();
+        let mut persistence = Persistence::new([2, 1]);
+        let mut lit = Luminance::new(2, 1);
+        lit[(0, 0)] = image::Luma([1.0]);
+        let first = persistence.update(&lit, &decay, Duration::from_secs(0));
+        assert_eq!(first.into_raw(), vec![1.0, 0.0]);
+        let elapsed = Duration::from_millis(100);
+        let dark = Luminance::new(2, 1);
+        let second = persistence.update(&dark, &decay, elapsed);
+        assert!((second[(0, 0)][0] - decay.brightness(elapsed)).abs() < 1e-6);
+        assert_eq!(second[(1, 0)][0], 0.0);
+    }
+}
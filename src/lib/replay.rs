@@ -0,0 +1,363 @@
+//! Items related to replaying a recorded serial capture as a frame source.
+
+use crate::capture;
+use crate::serial::FrameSource;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// The longest the source will sleep before re-checking the playback controls.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// How long a read may wait without producing any bytes before timing out, matching the serial port.
+// Reads made while paused instead return `Interrupted` after this long so that the receiving thread
+// may handle its controls.
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A `FrameSource` that feeds a capture file back through the frame decoder with the timing at
+/// which the bytes were originally received.
+pub struct ReplaySource {
+    reader: capture::Reader,
+    controls: Arc<Controls>,
+    // The chunk currently being read along with the index of the next byte to read.
+    pending: Option<(capture::Chunk, usize)>,
+    // The instant and capture position from which the timing of subsequent chunks is measured,
+    // along with the speed at the time.
+    anchor: Option<Anchor>,
+}
+
+/// Controls shared between a `ReplaySource` and the main thread.
+#[derive(Debug)]
+pub struct Controls {
+    duration: Duration,
+    state: Mutex<State>,
+    // Notified whenever the playback changes or a seek is requested, waking a paused replay.
+    changed: Condvar,
+}
+
+/// How the capture should be played back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Playback {
+    /// Scales the rate at which the capture is played back, where `1.0` is the original timing.
+    pub speed: f32,
+    /// Whether or not playback is paused.
+    pub paused: bool,
+    /// Whether or not to return to the beginning upon reaching the end of the capture.
+    pub looping: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    playback: Playback,
+    position: Duration,
+    seek: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Anchor {
+    instant: Instant,
+    position: Duration,
+    speed: f32,
+}
+
+impl ReplaySource {
+    /// Open the capture file at the given path for replay.
+    pub fn open(path: PathBuf, playback: Playback) -> io::Result<Self> {
+        let reader = capture::Reader::open(path)?;
+        let state = Mutex::new(State {
+            playback,
+            position: Duration::from_secs(0),
+            seek: None,
+        });
+        let duration = reader.duration();
+        let changed = Condvar::new();
+        let controls = Arc::new(Controls {
+            duration,
+            state,
+            changed,
+        });
+        Ok(ReplaySource {
+            reader,
+            controls,
+            pending: None,
+            anchor: None,
+        })
+    }
+
+    /// Controls for the replay that may be used from another thread once the source is spawned.
+    pub fn controls(&self) -> Arc<Controls> {
+        self.controls.clone()
+    }
+
+    // Jump to the given position within the capture.
+    fn seek(&mut self, position: Duration) -> io::Result<()> {
+        self.reader.seek(position)?;
+        self.pending = None;
+        self.anchor = None;
+        self.controls.state.lock().unwrap().position = position;
+        Ok(())
+    }
+}
+
+impl Controls {
+    /// The total duration of the capture.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The timestamp of the most recently replayed chunk.
+    pub fn position(&self) -> Duration {
+        self.state.lock().unwrap().position
+    }
+
+    /// The current playback settings.
+    pub fn playback(&self) -> Playback {
+        self.state.lock().unwrap().playback
+    }
+
+    /// Update the playback settings.
+    pub fn set_playback(&self, playback: Playback) {
+        self.state.lock().unwrap().playback = playback;
+        self.changed.notify_all();
+    }
+
+    /// Request that the replay jump to the given position.
+    pub fn seek(&self, position: Duration) {
+        let position = std::cmp::min(position, self.duration);
+        let mut state = self.state.lock().unwrap();
+        state.seek = Some(position);
+        state.position = position;
+        self.changed.notify_all();
+    }
+}
+
+impl Playback {
+    // Whether or not playback is stopped, either by pausing or by a speed of zero.
+    fn is_stopped(&self) -> bool {
+        self.paused || self.speed <= 0.0
+    }
+}
+
+impl Anchor {
+    // The instant at which the chunk with the given timestamp is due to be replayed.
+    fn due(&self, timestamp: Duration) -> Instant {
+        let elapsed = timestamp.checked_sub(self.position).unwrap_or_default();
+        self.instant + elapsed.div_f32(self.speed)
+    }
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            speed: 1.0,
+            paused: false,
+            looping: false,
+        }
+    }
+}
+
+impl io::Read for ReplaySource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut start = Instant::now();
+        loop {
+            if start.elapsed() > READ_TIMEOUT {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "replay timed out"));
+            }
+
+            let (playback, seek) = {
+                let mut state = self.controls.state.lock().unwrap();
+                (state.playback, state.seek.take())
+            };
+            if let Some(position) = seek {
+                self.seek(position)?;
+            }
+
+            // While paused, wait for playback to resume or a seek. Pausing is not a timeout, so the
+            // read is interrupted instead if neither occurs in time.
+            if playback.is_stopped() {
+                self.anchor = None;
+                let state = self.controls.state.lock().unwrap();
+                if state.playback.is_stopped() && state.seek.is_none() {
+                    let changed = &self.controls.changed;
+                    let (state, _) = changed.wait_timeout(state, READ_TIMEOUT).unwrap();
+                    if state.playback.is_stopped() && state.seek.is_none() {
+                        return Err(io::Error::new(io::ErrorKind::Interrupted, "replay paused"));
+                    }
+                }
+                start = Instant::now();
+                continue;
+            }
+
+            // Retrieve the next chunk, returning to the beginning if looping.
+            if self.pending.is_none() {
+                match self.reader.next_chunk()? {
+                    Some(chunk) => self.pending = Some((chunk, 0)),
+                    None if playback.looping && self.reader.chunk_count() > 0 => {
+                        self.seek(Duration::from_secs(0))?;
+                        continue;
+                    }
+                    None => return Ok(0),
+                }
+            }
+            let (chunk, offset) = self.pending.as_mut().unwrap();
+
+            // Re-anchor the timing upon starting, resuming or changing speed.
+            let anchor = match self.anchor {
+                Some(anchor) if anchor.speed == playback.speed => anchor,
+                _ => {
+                    let anchor = Anchor {
+                        instant: Instant::now(),
+                        position: chunk.timestamp,
+                        speed: playback.speed,
+                    };
+                    self.anchor = Some(anchor);
+                    anchor
+                }
+            };
+
+            // Wait until the chunk is due.
+            let due = anchor.due(chunk.timestamp);
+            let now = Instant::now();
+            if due > now {
+                std::thread::sleep(std::cmp::min(due - now, POLL_INTERVAL));
+                continue;
+            }
+
+            // Copy as much of the chunk as will fit.
+            let remaining = &chunk.bytes[*offset..];
+            let len = std::cmp::min(remaining.len(), buf.len());
+            buf[..len].copy_from_slice(&remaining[..len]);
+            *offset += len;
+            self.controls.state.lock().unwrap().position = chunk.timestamp;
+            if *offset == chunk.bytes.len() {
+                self.pending = None;
+            }
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+impl FrameSource for ReplaySource {
+    fn description(&self) -> String {
+        let name = self.reader.path().file_name().unwrap_or_default();
+        format!("replay of {}", name.to_string_lossy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    // A replay of chunks `[0]`, `[1]` and `[2]` received 40ms apart.
+    fn open_replay(name: &str, playback: Playback) -> ReplaySource {
+        let mut bytes = capture::MAGIC.to_vec();
+        for ix in 0..3u8 {
+            let nanos = Duration::from_millis(ix as u64 * 40).as_nanos() as u64;
+            bytes.extend_from_slice(&nanos.to_le_bytes());
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes.push(ix);
+        }
+        let ext = capture::FILE_EXTENSION;
+        let name = format!("cbm8032_replay_{}_{}.{}", name, std::process::id(), ext);
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, &bytes).unwrap();
+        let replay = ReplaySource::open(path.clone(), playback).unwrap();
+        // The reader keeps the file open, so it may be removed straight away on unix.
+        std::fs::remove_file(&path).ok();
+        replay
+    }
+
+    fn read_byte(replay: &mut ReplaySource) -> io::Result<Option<u8>> {
+        let mut buf = [0u8; 8];
+        let len = replay.read(&mut buf)?;
+        assert!(len <= 1);
+        Ok(buf[..len].first().cloned())
+    }
+
+    #[test]
+    fn anchor_scales_by_speed() {
+        let instant = Instant::now();
+        let anchor = Anchor {
+            instant,
+            position: Duration::from_secs(1),
+            speed: 2.0,
+        };
+        assert_eq!(anchor.due(Duration::from_secs(3)), instant + Duration::from_secs(1));
+        // Chunks preceding the anchor are due immediately.
+        assert_eq!(anchor.due(Duration::from_millis(500)), instant);
+    }
+
+    #[test]
+    fn chunks_replay_with_original_timing() {
+        let mut replay = open_replay("timing", Playback::default());
+        let start = Instant::now();
+        for ix in 0..3 {
+            assert_eq!(read_byte(&mut replay).unwrap(), Some(ix));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(80));
+        assert_eq!(read_byte(&mut replay).unwrap(), None);
+        assert_eq!(replay.controls().position(), Duration::from_millis(80));
+    }
+
+    #[test]
+    fn speed_shortens_replay() {
+        let playback = Playback {
+            speed: 4.0,
+            ..Playback::default()
+        };
+        let mut replay = open_replay("speed", playback);
+        let start = Instant::now();
+        for ix in 0..3 {
+            assert_eq!(read_byte(&mut replay).unwrap(), Some(ix));
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(80), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn seek_jumps_to_chunk() {
+        let mut replay = open_replay("seek", Playback::default());
+        let controls = replay.controls();
+        controls.seek(Duration::from_millis(40));
+        assert_eq!(controls.position(), Duration::from_millis(40));
+        assert_eq!(read_byte(&mut replay).unwrap(), Some(1));
+        // Seeking beyond the end is clamped to the duration.
+        controls.seek(Duration::from_secs(10));
+        assert_eq!(controls.position(), controls.duration());
+        assert_eq!(read_byte(&mut replay).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn looping_returns_to_beginning() {
+        let playback = Playback {
+            speed: 8.0,
+            looping: true,
+            ..Playback::default()
+        };
+        let mut replay = open_replay("loop", playback);
+        let bytes: Vec<_> = (0..5).map(|_| read_byte(&mut replay).unwrap()).collect();
+        assert_eq!(bytes, vec![Some(0), Some(1), Some(2), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn resuming_wakes_paused_read() {
+        let playback = Playback {
+            paused: true,
+            ..Playback::default()
+        };
+        let mut replay = open_replay("pause", playback);
+        let controls = replay.controls();
+        let resume = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            controls.set_playback(Playback::default());
+        });
+        let start = Instant::now();
+        assert_eq!(read_byte(&mut replay).unwrap(), Some(0));
+        assert!(start.elapsed() < READ_TIMEOUT);
+        resume.join().unwrap();
+    }
+}
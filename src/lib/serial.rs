@@ -1,16 +1,25 @@
-//! Items related to receiving CBM 8032 frame data over serial.
+//! Items related to receiving CBM 8032 frame data over serial or any other byte source.
 
+use crate::capture;
+use crate::decode::{self, Checksum, DecodeEvent, Decoder};
 use crate::fps::Fps;
+use crate::link;
+use crate::net;
 use crate::vis;
-use serialport::prelude::*;
+use serde::{Deserialize, Serialize};
+use serialport::{SerialPort, SerialPortInfo, SerialPortSettings};
 use std::cell::RefCell;
-use std::io::{self, Write};
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{self, AtomicBool};
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
-const BAUD_RATE: u32 = 1_500_000;
-const DATA_PER_BUFFER: u32 = 40;
-const TOTAL_BUFFERS_PER_FRAME: u32 = 51;
+/// The size of the buffer into which sources are read when not otherwise specified.
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 256;
+/// The number of recently received frames retained by each `Handle`, roughly ten seconds at 60 Hz.
+pub const HISTORY_LEN: usize = 600;
 
 /// A handle to the receiving serial thread.
 pub struct Handle {
@@ -18,45 +27,189 @@ pub struct Handle {
     thread: std::thread::JoinHandle<()>,
     rx: ChannelRx,
     last_recorded_frame_hz: RefCell<FrameHz>,
-    port_info: SerialPortInfo,
+    description: String,
+    line_settings: Option<LineSettings>,
+    source_info: Arc<Mutex<SourceInfo>>,
+    link_stats: Arc<Mutex<link::Stats>>,
+    control_tx: ControlTx,
+    // The path of the capture in progress, owned by the receiving thread.
+    capture_path: Arc<Mutex<Option<PathBuf>>>,
+    capture_requested: RefCell<bool>,
+    broadcast: RefCell<Option<String>>,
+    checksum: RefCell<Checksum>,
+    partial_recovery: RefCell<bool>,
+    // The most recently received frames along with the time at which each was decoded.
+    history: RefCell<VecDeque<(Instant, vis::Cbm8032Frame)>>,
 }
 
-enum State {
-    CountingZeros,
-    InSync,
+/// A source of raw bytes in the CBM 8032 serial wire format.
+///
+/// The receiving thread reads from the source and runs the bytes through the frame decoder, so any
+/// transport (serial, TCP, UDP, file, pipe, in-memory) may be used as long as it can be read from.
+/// A read that fails with `TimedOut` is counted as a timeout, while one that fails with
+/// `Interrupted` is silently retried.
+pub trait FrameSource: io::Read + Send + 'static {
+    /// A short human-readable description of the source, e.g. for display in the GUI.
+    fn description(&self) -> String;
+
+    /// Information about the serial port if the source is one.
+    fn port_info(&self) -> Option<&SerialPortInfo> {
+        None
+    }
+
+    /// The line settings with which the serial port was opened if the source is one.
+    fn line_settings(&self) -> Option<&LineSettings> {
+        None
+    }
+
+    /// The outcome of requesting low latency mode if the source is a serial port.
+    fn low_latency(&self) -> Option<&LowLatency> {
+        None
+    }
+
+    /// The maximum number of bytes requested from the source by each read.
+    fn read_buffer_size(&self) -> usize {
+        DEFAULT_READ_BUFFER_SIZE
+    }
+
+    /// Attempt to re-establish the source after an error occurred while reading from it.
+    ///
+    /// By default sources cannot be re-established and the receiving thread will close.
+    fn reconnect(&mut self) -> io::Result<()> {
+        let desc = format!("{} cannot be re-established", self.description());
+        Err(io::Error::new(io::ErrorKind::Other, desc))
+    }
+}
+
+// Details of the source that may change when the receiving thread re-establishes it.
+#[derive(Default)]
+struct SourceInfo {
+    port_info: Option<SerialPortInfo>,
+    low_latency: Option<LowLatency>,
+}
+
+/// A `FrameSource` reading from a USB serial port.
+pub struct SerialSource {
+    port: Box<SerialPortObj>,
+    info: SerialPortInfo,
+    selection: PortSelection,
+    settings: LineSettings,
+    low_latency: LowLatency,
+}
+
+/// The outcome of requesting the `ASYNC_LOW_LATENCY` mode of the serial driver when opening a port.
+///
+/// Low latency mode stops the driver from batching received bytes, greatly reducing the jitter
+/// with which frames arrive.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LowLatency {
+    /// The driver reports that low latency mode is active.
+    Active,
+    /// Low latency mode could not be enabled for the given reason.
+    Failed(String),
+    /// Low latency mode is not supported on this platform.
+    Unsupported,
+}
+
+/// The serial line parameters with which the port is opened.
+///
+/// The defaults match the CBM 8032 hardware.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LineSettings {
+    pub baud_rate: u32,
+    /// The number of data bits per character, from 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// The number of stop bits, either 1 or 2.
+    pub stop_bits: u8,
+    pub flow_control: FlowControl,
+    /// How long a read may wait for data before timing out.
+    pub read_timeout_ms: u64,
+    /// The maximum number of bytes requested from the port by each read.
+    pub read_buffer_size: usize,
+}
+
+/// The parity checking mode of the serial line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// The flow control mode of the serial line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+/// Describes the serial port from which frames should be read.
+///
+/// Matching by USB VID, PID and serial number allows for finding the same device even if it is
+/// assigned a different port name after being unplugged and plugged back in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PortSelection {
+    /// The first available USB serial port.
+    FirstUsb,
+    /// The port with the given name, e.g. `/dev/ttyUSB0` or `COM3`.
+    Name(String),
+    /// The USB port with the given vendor and product IDs and, if specified, serial number.
+    Usb {
+        vid: u16,
+        pid: u16,
+        serial_number: Option<String>,
+    },
+}
+
+/// A `FrameSource` wrapping any type implementing `std::io::Read`.
+///
+/// Useful for reading frames from files, pipes, sockets or in-memory buffers. Reaching the end of
+/// the reader closes the receiving thread.
+pub struct ReadSource<R> {
+    reader: R,
+    description: String,
 }
 
 struct ReceiverContext {
-    rx_buffer: [u8; 256],
-    rx_buffer_index: u32,
-    rx_buffer_count: u32,
-    state: State,
-    bufnum: u32,
-    count: u32,
-    buffer: [u8; 40],
-    screen_buffer: Box<vis::Cbm8032FrameData>,
-    graphic: vis::Cbm8032FrameMode,
-}
-
-fn init_receiver_context() -> ReceiverContext {
+    rx_buffer: Vec<u8>,
+    decoder: Decoder,
+    // Decoded frames along with the buffers that changed since the previous frame.
+    frames: VecDeque<(vis::Cbm8032Frame, vis::BufferMask)>,
+    // Buffers that changed since the last frame was queued.
+    dirty: vis::BufferMask,
+}
+
+fn init_receiver_context(read_buffer_size: usize) -> ReceiverContext {
     ReceiverContext {
-        rx_buffer: [0u8; 256],
-        rx_buffer_index: 0,
-        rx_buffer_count: 0,
-        bufnum: 0,
-        count: 0,
-        state: State::CountingZeros,
-        buffer: [0u8; 40],
-        screen_buffer: Box::new([0u8; vis::CBM_8032_FRAME_DATA_LEN]),
-        graphic: vis::Cbm8032FrameMode::Graphics,
+        rx_buffer: vec![0u8; read_buffer_size.max(1)],
+        decoder: Decoder::new(),
+        frames: VecDeque::new(),
+        // The main thread's frame is unknown, so the first frame is sent in full.
+        dirty: decode::ALL_BUFFERS,
     }
 }
 
-type Message = (vis::Cbm8032Frame, FrameHz);
+type Message = (vis::FrameDelta, FrameHz, Instant);
 type ChannelRx = mpsc::Receiver<Message>;
 type ChannelTx = mpsc::Sender<Message>;
+type ControlRx = mpsc::Receiver<Control>;
+type ControlTx = mpsc::Sender<Control>;
 type SerialPortObj = dyn SerialPort;
 
+// Messages sent from the main thread to control the receiving thread.
+enum Control {
+    StartCapture(PathBuf),
+    StopCapture,
+    StartBroadcast(net::Sink),
+    StopBroadcast,
+    SetChecksum(Checksum),
+    SetPartialRecovery(bool),
+    RequestFullFrame,
+}
+
 /// The rate at which the serial stream is producing frames.
 #[derive(Clone, Copy, Default)]
 pub struct FrameHz {
@@ -66,13 +219,44 @@ pub struct FrameHz {
 }
 
 impl Handle {
-    /// Checks the queue for a pending frame and returns it.
-    pub fn try_recv_frame(&self) -> Option<vis::Cbm8032Frame> {
-        if let Some((frame, hz)) = self.rx.try_iter().last() {
+    /// Checks the queue for pending frames and returns the changes they describe.
+    ///
+    /// If more than one frame is pending, their deltas are merged into one. The first delta
+    /// received from a handle describes every buffer.
+    pub fn try_recv_delta(&self) -> Option<vis::FrameDelta> {
+        let mut merged: Option<vis::FrameDelta> = None;
+        for (delta, hz, instant) in self.rx.try_iter() {
             *self.last_recorded_frame_hz.borrow_mut() = hz;
-            return Some(frame);
+            self.record_history(&delta, instant);
+            match merged {
+                None => merged = Some(delta),
+                Some(ref mut merged) => merged.merge(delta),
+            }
         }
-        None
+        merged
+    }
+
+    /// The most recently received frames, oldest first, along with the time at which each was
+    /// decoded.
+    ///
+    /// Up to `HISTORY_LEN` frames are retained. Frames are only recorded as they are received via
+    /// `try_recv_delta`.
+    pub fn history(&self) -> Vec<(Instant, vis::Cbm8032Frame)> {
+        self.history.borrow().iter().cloned().collect()
+    }
+
+    // Append the frame described by the delta to the history, discarding the oldest if full.
+    fn record_history(&self, delta: &vis::FrameDelta, instant: Instant) {
+        let mut history = self.history.borrow_mut();
+        let mut frame = match history.back() {
+            Some((_, frame)) => frame.clone(),
+            None => vis::Cbm8032Frame::blank_graphics(),
+        };
+        delta.apply(&mut frame);
+        if history.len() >= HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back((instant, frame));
     }
 
     /// Produces the last frame sending rate sent by the serial thread.
@@ -80,6 +264,97 @@ impl Handle {
         *self.last_recorded_frame_hz.borrow()
     }
 
+    /// Begin recording every byte read from the source to a new capture file at the given path.
+    ///
+    /// If a capture is already in progress it is finished before the new one begins.
+    pub fn start_capture(&self, path: PathBuf) {
+        if self.control_tx.send(Control::StartCapture(path)).is_ok() {
+            *self.capture_requested.borrow_mut() = true;
+        }
+    }
+
+    /// Finish the capture in progress, if any.
+    pub fn stop_capture(&self) {
+        self.control_tx.send(Control::StopCapture).ok();
+        *self.capture_requested.borrow_mut() = false;
+    }
+
+    /// Whether or not a capture has been requested via `start_capture` and not since stopped.
+    ///
+    /// The capture may have failed to start or have been finished early due to an error, in which
+    /// case `is_capturing` returns `false`.
+    pub fn is_capture_requested(&self) -> bool {
+        *self.capture_requested.borrow()
+    }
+
+    /// The path of the capture file currently being recorded, if any.
+    pub fn capture_path(&self) -> Option<PathBuf> {
+        self.capture_path.lock().unwrap().clone()
+    }
+
+    /// Whether or not the raw stream is currently being captured.
+    pub fn is_capturing(&self) -> bool {
+        self.capture_path.lock().unwrap().is_some()
+    }
+
+    /// Begin republishing every decoded frame to the given network sink.
+    ///
+    /// If a broadcast is already in progress it is replaced by the new sink.
+    pub fn start_broadcast(&self, sink: net::Sink) {
+        let description = sink.description();
+        if self.control_tx.send(Control::StartBroadcast(sink)).is_ok() {
+            *self.broadcast.borrow_mut() = Some(description);
+        }
+    }
+
+    /// Stop republishing decoded frames, if we were.
+    pub fn stop_broadcast(&self) {
+        self.control_tx.send(Control::StopBroadcast).ok();
+        *self.broadcast.borrow_mut() = None;
+    }
+
+    /// A description of the sink to which frames are being republished, if any.
+    pub fn broadcast(&self) -> Option<String> {
+        self.broadcast.borrow().clone()
+    }
+
+    /// Whether or not decoded frames are being republished to the network.
+    pub fn is_broadcasting(&self) -> bool {
+        self.broadcast.borrow().is_some()
+    }
+
+    /// Switch the decoder to the protocol variant with the given checksum.
+    pub fn set_checksum(&self, checksum: Checksum) {
+        if self.control_tx.send(Control::SetChecksum(checksum)).is_ok() {
+            *self.checksum.borrow_mut() = checksum;
+        }
+    }
+
+    /// The checksum the decoder expects to follow each bufnum.
+    pub fn checksum(&self) -> Checksum {
+        *self.checksum.borrow()
+    }
+
+    /// Enable or disable publishing partial frames upon losing sync.
+    pub fn set_partial_recovery(&self, enabled: bool) {
+        if self.control_tx.send(Control::SetPartialRecovery(enabled)).is_ok() {
+            *self.partial_recovery.borrow_mut() = enabled;
+        }
+    }
+
+    /// Whether or not partial frames are published upon losing sync.
+    pub fn partial_recovery(&self) -> bool {
+        *self.partial_recovery.borrow()
+    }
+
+    /// Request that the next frame received describes every buffer.
+    ///
+    /// Useful after editing the visualised frame locally, as later deltas only describe the
+    /// buffers that changed on the PET and would otherwise leave the edit on screen.
+    pub fn request_full_frame(&self) {
+        self.control_tx.send(Control::RequestFullFrame).ok();
+    }
+
     /// Close the receiving thread.
     pub fn close(self) {
         self.is_closed.store(true, atomic::Ordering::SeqCst);
@@ -88,9 +363,37 @@ impl Handle {
         }
     }
 
-    /// Information about the connected serial port.
-    pub fn port_info(&self) -> &SerialPortInfo {
-        &self.port_info
+    /// A short human-readable description of the source that frames are being read from.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Information about the connected serial port, if the source is one.
+    ///
+    /// This is updated whenever the port is re-established, as it may be assigned a new name.
+    pub fn port_info(&self) -> Option<SerialPortInfo> {
+        self.source_info.lock().unwrap().port_info.clone()
+    }
+
+    /// The line settings with which the serial port was opened, if the source is one.
+    pub fn line_settings(&self) -> Option<&LineSettings> {
+        self.line_settings.as_ref()
+    }
+
+    /// The outcome of requesting low latency mode when the serial port was last opened, if the
+    /// source is one.
+    pub fn low_latency(&self) -> Option<LowLatency> {
+        self.source_info.lock().unwrap().low_latency.clone()
+    }
+
+    /// A snapshot of the health statistics for the link with the source.
+    pub fn link_stats(&self) -> link::Stats {
+        self.link_stats.lock().unwrap().clone()
+    }
+
+    /// Reset the link statistics, e.g. after reseating a cable.
+    pub fn reset_link_stats(&self) {
+        *self.link_stats.lock().unwrap() = link::Stats::default();
     }
 
     /// Whether or not the stream has closed.
@@ -101,172 +404,483 @@ impl Handle {
     }
 }
 
-fn find_usb_port() -> Result<Option<SerialPortInfo>, serialport::Error> {
-    let infos = serialport::available_ports()?;
-    let info = infos
-        .into_iter()
-        .filter(|info| match info.port_type {
-            serialport::SerialPortType::UsbPort(_) => true,
-            _ => false,
+impl SourceInfo {
+    fn of<S: FrameSource>(source: &S) -> Self {
+        SourceInfo {
+            port_info: source.port_info().cloned(),
+            low_latency: source.low_latency().cloned(),
+        }
+    }
+}
+
+impl SerialSource {
+    /// Open the serial port described by the given info with the given line settings.
+    pub fn open(info: SerialPortInfo, settings: LineSettings) -> Result<Self, serialport::Error> {
+        let selection = PortSelection::Name(info.port_name.clone());
+        let (port, low_latency) = open_port(&info.port_name, &settings)?;
+        Ok(SerialSource {
+            port,
+            info,
+            selection,
+            settings,
+            low_latency,
         })
-        .next();
-    Ok(info)
-}
-
-fn port_settings() -> SerialPortSettings {
-    let mut settings = SerialPortSettings::default();
-    settings.baud_rate = BAUD_RATE.into();
-    settings.timeout = std::time::Duration::from_secs(1);
-    settings
-}
-
-fn open_port(name: &str) -> Result<Box<SerialPortObj>, serialport::Error> {
-    if cfg!(target_os = "linux") {
-        let res = std::process::Command::new("setserial")
-            .arg(&name)
-            .arg("low_latency")
-            .output();
-        match res {
-            Ok(output) => {
-                if !output.status.success() {
-                    eprintln!("`setserial {} low_latency` was unsuccessful", name);
+    }
+
+    /// Open the first available serial port matching the given selection.
+    ///
+    /// The selection is also used to find the port again when re-establishing the connection.
+    pub fn open_selection(
+        selection: &PortSelection,
+        settings: LineSettings,
+    ) -> Result<Self, serialport::Error> {
+        match find_port(selection)? {
+            Some(info) => {
+                let (port, low_latency) = open_port(&info.port_name, &settings)?;
+                let selection = selection.clone();
+                Ok(SerialSource {
+                    port,
+                    info,
+                    selection,
+                    settings,
+                    low_latency,
+                })
+            }
+            None => {
+                let desc = format!("no available serial port matching {}", selection);
+                let kind = serialport::ErrorKind::NoDevice;
+                Err(serialport::Error::new(kind, desc))
+            }
+        }
+    }
+}
+
+impl io::Read for SerialSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl FrameSource for SerialSource {
+    fn description(&self) -> String {
+        format!("serial port {:?}", self.info.port_name)
+    }
+
+    fn port_info(&self) -> Option<&SerialPortInfo> {
+        Some(&self.info)
+    }
+
+    fn line_settings(&self) -> Option<&LineSettings> {
+        Some(&self.settings)
+    }
+
+    fn low_latency(&self) -> Option<&LowLatency> {
+        Some(&self.low_latency)
+    }
+
+    fn read_buffer_size(&self) -> usize {
+        self.settings.read_buffer_size
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        let attempts = 3;
+        let mut attempt = 0;
+        loop {
+            println!("Attempting to re-establish connection with {}", self.selection);
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            // The port may have been assigned a new name, so search for it again.
+            let res = find_port(&self.selection).and_then(|info| {
+                let info = info.ok_or_else(|| {
+                    let desc = format!("no available serial port matching {}", self.selection);
+                    serialport::Error::new(serialport::ErrorKind::NoDevice, desc)
+                })?;
+                let (port, low_latency) = open_port(&info.port_name, &self.settings)?;
+                Ok((port, info, low_latency))
+            });
+            match res {
+                Ok((port, info, low_latency)) => {
+                    self.port = port;
+                    self.info = info;
+                    self.low_latency = low_latency;
+                    return Ok(());
                 }
-                if !output.stderr.is_empty() {
-                    io::stderr().write_all(&output.stderr).ok();
+                Err(err) => {
+                    eprintln!("failed to connect to port: {}", err);
+                    attempt += 1;
+                    if attempt == attempts {
+                        return Err(err.into());
+                    }
                 }
             }
-            Err(err) => {
-                eprintln!("failed to execute command `setserial {} low_latency`: {}", name, err);
+        }
+    }
+}
+
+impl<R> ReadSource<R> {
+    /// Wrap the given reader, described by `description` for display purposes.
+    pub fn new(reader: R, description: impl Into<String>) -> Self {
+        let description = description.into();
+        ReadSource { reader, description }
+    }
+}
+
+impl<R: io::Read> io::Read for ReadSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: io::Read + Send + 'static> FrameSource for ReadSource<R> {
+    fn description(&self) -> String {
+        self.description.clone()
+    }
+}
+
+impl PortSelection {
+    /// A selection that will match the given port, preferring USB VID, PID and serial number.
+    pub fn for_port(info: &SerialPortInfo) -> Self {
+        match info.port_type {
+            serialport::SerialPortType::UsbPort(ref usb) if usb.serial_number.is_some() => {
+                PortSelection::Usb {
+                    vid: usb.vid,
+                    pid: usb.pid,
+                    serial_number: usb.serial_number.clone(),
+                }
+            }
+            // Without a serial number the VID and PID would match any identical adapter.
+            _ => PortSelection::Name(info.port_name.clone()),
+        }
+    }
+
+    /// Whether or not the given port matches the selection.
+    pub fn matches(&self, info: &SerialPortInfo) -> bool {
+        match (self, &info.port_type) {
+            (PortSelection::FirstUsb, serialport::SerialPortType::UsbPort(_)) => true,
+            (PortSelection::Name(ref name), _) => *name == info.port_name,
+            (
+                PortSelection::Usb {
+                    vid,
+                    pid,
+                    ref serial_number,
+                },
+                serialport::SerialPortType::UsbPort(ref usb),
+            ) => {
+                *vid == usb.vid
+                    && *pid == usb.pid
+                    && (serial_number.is_none() || *serial_number == usb.serial_number)
             }
+            _ => false,
         }
     }
-    let settings = port_settings();
-    serialport::open_with_settings(&name, &settings)
 }
 
-fn byte_to_mode(byte: u8) -> vis::Cbm8032FrameMode {
-    match byte {
-        0 => vis::Cbm8032FrameMode::Graphics,
-        _ => vis::Cbm8032FrameMode::Text,
+impl Default for PortSelection {
+    fn default() -> Self {
+        PortSelection::FirstUsb
     }
 }
 
-fn handle_received_buffer(context: &mut ReceiverContext) {
-    if context.bufnum > 0 {
-        if context.bufnum < TOTAL_BUFFERS_PER_FRAME {
-            let bufidx = context.bufnum - 1;
-            let screen_start = (bufidx * DATA_PER_BUFFER) as usize;
-            let screen_end = screen_start + DATA_PER_BUFFER as usize;
-            let screen_slice = &mut context.screen_buffer[screen_start..screen_end];
-            screen_slice.copy_from_slice(&context.buffer);
-        } else {
-            context.graphic = byte_to_mode(context.buffer[0]);
+impl std::fmt::Display for PortSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PortSelection::FirstUsb => write!(f, "first USB port"),
+            PortSelection::Name(name) => write!(f, "{}", name),
+            PortSelection::Usb {
+                vid,
+                pid,
+                serial_number,
+            } => {
+                write!(f, "USB {:04x}:{:04x}", vid, pid)?;
+                if let Some(serial_number) = serial_number {
+                    write!(f, " {}", serial_number)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-fn handle_sync_loss(context: &ReceiverContext, byte: u8) {
+impl LineSettings {
+    /// The duration a read may wait for data before timing out.
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms)
+    }
+
+    /// The settings in the form expected by the `serialport` crate.
+    ///
+    /// Unsupported data or stop bit counts fall back to 8 and 1 respectively.
+    pub fn port_settings(&self) -> SerialPortSettings {
+        let data_bits = match self.data_bits {
+            5 => serialport::DataBits::Five,
+            6 => serialport::DataBits::Six,
+            7 => serialport::DataBits::Seven,
+            _ => serialport::DataBits::Eight,
+        };
+        let parity = match self.parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even,
+        };
+        let stop_bits = match self.stop_bits {
+            2 => serialport::StopBits::Two,
+            _ => serialport::StopBits::One,
+        };
+        let flow_control = match self.flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::Software => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware,
+        };
+        SerialPortSettings {
+            baud_rate: self.baud_rate,
+            data_bits,
+            flow_control,
+            parity,
+            stop_bits,
+            timeout: self.read_timeout(),
+        }
+    }
+}
+
+impl Default for LineSettings {
+    fn default() -> Self {
+        LineSettings {
+            baud_rate: 1_500_000,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            flow_control: FlowControl::None,
+            read_timeout_ms: 1_000,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+        }
+    }
+}
+
+/// Find the first available port matching the given selection.
+pub fn find_port(selection: &PortSelection) -> Result<Option<SerialPortInfo>, serialport::Error> {
+    let infos = serialport::available_ports()?;
+    Ok(infos.into_iter().find(|info| selection.matches(info)))
+}
+
+// Open the port, requesting low latency mode from the driver where supported.
+#[cfg(target_os = "linux")]
+fn open_port(
+    name: &str,
+    settings: &LineSettings,
+) -> Result<(Box<SerialPortObj>, LowLatency), serialport::Error> {
+    use std::os::unix::io::AsRawFd;
+    let settings = settings.port_settings();
+    let port = serialport::posix::TTYPort::open(Path::new(name), &settings)?;
+    let low_latency = match low_latency::enable(port.as_raw_fd()) {
+        Ok(true) => LowLatency::Active,
+        Ok(false) => LowLatency::Failed("the driver ignored the request".into()),
+        Err(err) => LowLatency::Failed(err.to_string()),
+    };
+    if let LowLatency::Failed(ref reason) = low_latency {
+        eprintln!("failed to enable low latency mode for {}: {}", name, reason);
+    }
+    Ok((Box::new(port), low_latency))
+}
+
+// Open the port, requesting low latency mode from the driver where supported.
+#[cfg(not(target_os = "linux"))]
+fn open_port(
+    name: &str,
+    settings: &LineSettings,
+) -> Result<(Box<SerialPortObj>, LowLatency), serialport::Error> {
+    let settings = settings.port_settings();
+    let port = serialport::open_with_settings(&name, &settings)?;
+    Ok((port, LowLatency::Unsupported))
+}
+
+fn handle_sync_loss(bufnum: u32, count: u32, byte: u8) {
     eprintln!(
         "out of sync at bufnum {} count {} - received {}\n",
-        context.bufnum, context.count, byte
+        bufnum, count, byte
     );
 }
 
-fn handle_received_byte(context: &mut ReceiverContext, byte: u8) -> bool {
-    let mut screen_complete = false;
-    match context.state {
-        State::CountingZeros => {
-            if byte == 0 {
-                context.count += 1;
-                if context.count == 41 {
-                    context.state = State::InSync;
-                    context.bufnum = 1;
-                    context.count = 0;
-                }
-            } else {
-                context.count = 0;
-            }
+// Read from the source until a full screen has been decoded.
+fn receive_screen<S>(
+    source: &mut S,
+    context: &mut ReceiverContext,
+    capture: &mut Option<capture::Writer>,
+    link_stats: &Mutex<link::Stats>,
+) -> io::Result<(vis::Cbm8032Frame, vis::BufferMask)>
+where
+    S: FrameSource,
+{
+    loop {
+        if let Some(frame) = context.frames.pop_front() {
+            return Ok(frame);
         }
-        State::InSync => {
-            if context.count < 40 {
-                context.buffer[context.count as usize] = byte;
-                context.count += 1;
-            } else {
-                if byte == context.bufnum as u8 {
-                    handle_received_buffer(context);
-                    context.bufnum += 1;
-                    context.count = 0;
-                    if context.bufnum == 52 {
-                        context.state = State::CountingZeros;
-                        screen_complete = true;
-                    }
-                } else {
-                    handle_sync_loss(context, byte);
-                    context.state = State::CountingZeros;
-                    context.count = 0;
+        let len = match source.read(&mut context.rx_buffer) {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+            Ok(0) => {
+                let desc = "reached the end of the frame source";
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, desc));
+            }
+            Ok(len) => len,
+        };
+        let bytes = &context.rx_buffer[..len];
+        write_capture_chunk(capture, bytes);
+        let mut stats = link_stats.lock().unwrap();
+        stats.record_read(len);
+        for event in context.decoder.push(bytes) {
+            match event {
+                DecodeEvent::FrameComplete(frame) => {
+                    stats.record_frame();
+                    let dirty = std::mem::replace(&mut context.dirty, 0);
+                    context.frames.push_back((frame, dirty));
+                }
+                DecodeEvent::SyncLost { bufnum, count, byte } => {
+                    stats.record_sync_loss(bufnum);
+                    handle_sync_loss(bufnum, count, byte);
                 }
+                DecodeEvent::PartialFrame(frame) => {
+                    stats.record_partial_frame();
+                    let dirty = std::mem::replace(&mut context.dirty, 0);
+                    context.frames.push_back((frame, dirty));
+                }
+                DecodeEvent::BuffersChanged(buffers) => context.dirty |= buffers,
+                // The previous data is kept. Mismatches are counted rather than logged, as a noisy
+                // link may produce many per frame.
+                DecodeEvent::BufferCorrupted { .. } => stats.record_corrupted_buffer(),
+                DecodeEvent::SyncAcquired | DecodeEvent::ModeChanged(_) => (),
             }
         }
     }
-    screen_complete
 }
 
-fn receive_screen(port: &mut Box<SerialPortObj>, context: &mut ReceiverContext) -> io::Result<()> {
-    loop {
-        if context.rx_buffer_index == context.rx_buffer_count {
-            context.rx_buffer_index = 0;
-            context.rx_buffer_count = match port.read(&mut context.rx_buffer) {
-                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => 0,
-                Err(err) => return Err(err),
-                Ok(len) => len as _,
-            };
-        } else {
-            let ix = context.rx_buffer_index;
-            context.rx_buffer_index += 1;
-            let received_byte = context.rx_buffer[ix as usize];
-            if handle_received_byte(context, received_byte) {
-                return Ok(());
-            }
+// Write the chunk to the capture file if there is one, finishing the capture on failure.
+fn write_capture_chunk(capture: &mut Option<capture::Writer>, bytes: &[u8]) {
+    if let Some(writer) = capture.as_mut() {
+        if let Err(err) = writer.write_chunk(bytes) {
+            eprintln!("failed to write to capture {}: {}", writer.path().display(), err);
+            capture.take();
+        }
+    }
+}
+
+// Create a new capture file at the given path.
+fn start_capture(path: &Path) -> Option<capture::Writer> {
+    match capture::Writer::create(path.to_path_buf()) {
+        Ok(writer) => Some(writer),
+        Err(err) => {
+            eprintln!("failed to create capture {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// Flush and close the capture file if there is one.
+fn finish_capture(capture: &mut Option<capture::Writer>) {
+    if let Some(mut writer) = capture.take() {
+        if let Err(err) = writer.flush() {
+            eprintln!("failed to flush capture {}: {}", writer.path().display(), err);
         }
     }
 }
 
-// Open the serial port and run the read loop.
-fn run(mut port: Box<SerialPortObj>, vis_frame_tx: ChannelTx, is_closed: Arc<AtomicBool>) {
-    let port_name = port.name();
+// Share the path of the capture in progress, if any, with the handle.
+fn publish_capture(capture: &Option<capture::Writer>, capture_path: &Mutex<Option<PathBuf>>) {
+    let path = capture.as_ref().map(|writer| writer.path());
+    let mut capture_path = capture_path.lock().unwrap();
+    if capture_path.as_deref() != path {
+        *capture_path = path.map(Path::to_path_buf);
+    }
+}
+
+// Run the read loop over the given source.
+fn run<S>(
+    mut source: S,
+    vis_frame_tx: ChannelTx,
+    control_rx: ControlRx,
+    is_closed: Arc<AtomicBool>,
+    source_info: Arc<Mutex<SourceInfo>>,
+    capture_path: Arc<Mutex<Option<PathBuf>>>,
+    link_stats: Arc<Mutex<link::Stats>>,
+) where
+    S: FrameSource,
+{
     let fps = Fps::default();
-    let mut context = init_receiver_context();
+    let mut context = init_receiver_context(source.read_buffer_size());
+    let mut capture = None;
+    let mut broadcast: Option<net::Sink> = None;
+    let mut frame_count = 0u64;
+    let mut full_frame_requested = false;
     while !is_closed.load(atomic::Ordering::Relaxed) {
-        if let Err(e) = receive_screen(&mut port, &mut context) {
-            if let io::ErrorKind::TimedOut = e.kind() {
-                eprintln!("No serial data received in the last second");
-                continue;
+        // Handle any pending messages from the main thread.
+        for control in control_rx.try_iter() {
+            match control {
+                Control::StartCapture(path) => {
+                    finish_capture(&mut capture);
+                    capture = start_capture(&path);
+                }
+                Control::StopCapture => finish_capture(&mut capture),
+                Control::StartBroadcast(sink) => broadcast = Some(sink),
+                Control::StopBroadcast => broadcast = None,
+                Control::SetChecksum(checksum) => context.decoder.set_checksum(checksum),
+                Control::SetPartialRecovery(enabled) => {
+                    context.decoder.set_partial_recovery(enabled)
+                }
+                Control::RequestFullFrame => full_frame_requested = true,
             }
-            eprintln!("An error occurred while reading from the serial port: {}", e);
-            if let Some(ref port_name) = port_name {
-                let attempts = 3;
-                for attempt in 0..attempts {
-                    println!("Attempting to re-establish connection with {:?}", port_name);
-                    std::thread::sleep(std::time::Duration::from_secs(1));
-                    match open_port(&port_name) {
-                        Ok(new_port) => port = new_port,
-                        Err(err) => {
-                            eprintln!("failed to connect to port: {}", err);
-                            if attempt < attempts - 1 {
-                                continue;
-                            }
-                            is_closed.store(true, atomic::Ordering::SeqCst);
-                        }
-                    };
-                    break;
+        }
+        publish_capture(&capture, &capture_path);
+
+        let received = receive_screen(&mut source, &mut context, &mut capture, &link_stats);
+        let (frame, dirty) = match received {
+            Ok(frame) => frame,
+            Err(e) => {
+                // The source returned without data for a reason other than an error, e.g. a paused
+                // replay, giving the chance to handle any controls.
+                if let io::ErrorKind::Interrupted = e.kind() {
+                    continue;
+                }
+                if let io::ErrorKind::TimedOut = e.kind() {
+                    link_stats.lock().unwrap().record_timeout();
+                    eprintln!("No data received from {} before timing out", source.description());
+                    continue;
+                }
+                eprintln!("An error occurred while reading from {}: {}", source.description(), e);
+                link_stats.lock().unwrap().record_reconnect();
+                match source.reconnect() {
+                    Ok(()) => *source_info.lock().unwrap() = SourceInfo::of(&source),
+                    Err(err) => {
+                        eprintln!("failed to re-establish {}: {}", source.description(), err);
+                        is_closed.store(true, atomic::Ordering::SeqCst);
+                    }
                 }
+                continue;
+            }
+        };
 
+        // Keep the capture file up to date with each completed frame.
+        if let Some(writer) = capture.as_mut() {
+            if let Err(err) = writer.flush() {
+                eprintln!("failed to flush capture {}: {}", writer.path().display(), err);
+                capture.take();
             }
-            continue;
         }
+        publish_capture(&capture, &capture_path);
+
+        // Only the buffers that changed are sent to the main thread, unless it asked for them all.
+        let dirty = if std::mem::replace(&mut full_frame_requested, false) {
+            decode::ALL_BUFFERS
+        } else {
+            dirty
+        };
+        let delta = vis::FrameDelta::new(&frame, dirty);
 
-        // Construct the frame.
-        let frame = vis::Cbm8032Frame::new(context.graphic, context.screen_buffer.clone());
+        // Republish the frame to the network.
+        if let Some(sink) = broadcast.as_mut() {
+            let packet = net::Packet::now(frame_count, frame);
+            if let Err(err) = sink.send(&packet) {
+                eprintln!("failed to broadcast frame to {}: {}", sink.description(), err);
+            }
+        }
+        frame_count = frame_count.wrapping_add(1);
 
         // Sample the rate at which serial data is producing frames.
         fps.sample();
@@ -275,38 +889,195 @@ fn run(mut port: Box<SerialPortObj>, vis_frame_tx: ChannelTx, is_closed: Arc<Ato
         let max = fps.max();
         let hz = FrameHz { avg, min, max };
 
-        // Send the frame to the main thread.
-        if vis_frame_tx.send((frame, hz)).is_err() {
+        // Send the changes to the main thread.
+        if vis_frame_tx.send((delta, hz, Instant::now())).is_err() {
             eprintln!("lost connecton to main thread, closing serial thread");
             return;
         }
     }
 }
 
-/// Spawn a thread for receiving serial data.
-pub fn spawn() -> Result<Handle, serialport::Error> {
+/// Spawn a thread for receiving serial data from the first port matching the given selection.
+///
+/// The stream is decoded with the given checksum and partial frame recovery, which may later be
+/// changed via the returned `Handle`.
+pub fn spawn(
+    selection: &PortSelection,
+    settings: &LineSettings,
+    checksum: Checksum,
+    partial_recovery: bool,
+) -> Result<Handle, serialport::Error> {
+    let source = SerialSource::open_selection(selection, settings.clone())?;
+    Ok(spawn_source(source, checksum, partial_recovery))
+}
+
+/// Spawn a thread for receiving frames from the given source.
+///
+/// The stream is decoded with the given checksum and partial frame recovery, which may later be
+/// changed via the returned `Handle`.
+pub fn spawn_source<S>(source: S, checksum: Checksum, partial_recovery: bool) -> Handle
+where
+    S: FrameSource,
+{
     let is_closed = Arc::new(AtomicBool::new(false));
     let is_closed2 = is_closed.clone();
     let (tx, rx) = mpsc::channel();
-    let info = match find_usb_port()? {
-        Some(info) => info,
-        None => {
-            let desc = "no available serial USB ports".to_string();
-            let kind = serialport::ErrorKind::NoDevice;
-            return Err(serialport::Error::new(kind, desc));
-        }
-    };
-    let port = open_port(&info.port_name)?;
+    let (control_tx, control_rx) = mpsc::channel();
+    // Pending controls are handled before the first read, so no bytes are decoded without these.
+    control_tx.send(Control::SetChecksum(checksum)).unwrap();
+    control_tx.send(Control::SetPartialRecovery(partial_recovery)).unwrap();
+    let description = source.description();
+    let line_settings = source.line_settings().cloned();
+    let source_info = Arc::new(Mutex::new(SourceInfo::of(&source)));
+    let source_info2 = source_info.clone();
+    let capture_path = Arc::new(Mutex::new(None));
+    let capture_path2 = capture_path.clone();
+    let link_stats = Arc::new(Mutex::new(link::Stats::default()));
+    let link_stats2 = link_stats.clone();
     let thread = std::thread::Builder::new()
         .name("serial_rx_thread".into())
-        .spawn(move || run(port, tx, is_closed2))
+        .spawn(move || {
+            run(source, tx, control_rx, is_closed2, source_info2, capture_path2, link_stats2)
+        })
         .expect("failed to spawn serial rx thread");
     let last_recorded_frame_hz = RefCell::new(FrameHz::default());
-    Ok(Handle {
+    let capture_requested = RefCell::new(false);
+    let broadcast = RefCell::new(None);
+    let checksum = RefCell::new(checksum);
+    let partial_recovery = RefCell::new(partial_recovery);
+    let history = RefCell::new(VecDeque::with_capacity(HISTORY_LEN));
+    Handle {
         is_closed,
         rx,
         thread,
         last_recorded_frame_hz,
-        port_info: info,
-    })
+        description,
+        line_settings,
+        source_info,
+        link_stats,
+        control_tx,
+        capture_path,
+        capture_requested,
+        broadcast,
+        checksum,
+        partial_recovery,
+        history,
+    }
+}
+
+// Direct access to the `ASYNC_LOW_LATENCY` flag of the Linux serial driver, equivalent to
+// `setserial <port> low_latency`.
+#[cfg(target_os = "linux")]
+mod low_latency {
+    use std::io;
+    use std::os::unix::io::RawFd;
+
+    // From `asm-generic/ioctls.h` and `linux/tty_flags.h`.
+    const TIOCGSERIAL: u32 = 0x541E;
+    const TIOCSSERIAL: u32 = 0x541F;
+    const ASYNC_LOW_LATENCY: libc::c_int = 1 << 13;
+
+    // Mirrors `struct serial_struct` from `linux/serial.h`.
+    #[repr(C)]
+    struct SerialStruct {
+        kind: libc::c_int,
+        line: libc::c_int,
+        port: libc::c_uint,
+        irq: libc::c_int,
+        flags: libc::c_int,
+        xmit_fifo_size: libc::c_int,
+        custom_divisor: libc::c_int,
+        baud_base: libc::c_int,
+        close_delay: libc::c_ushort,
+        io_type: libc::c_char,
+        reserved_char: [libc::c_char; 1],
+        hub6: libc::c_int,
+        closing_wait: libc::c_ushort,
+        closing_wait2: libc::c_ushort,
+        iomem_base: *mut libc::c_uchar,
+        iomem_reg_shift: libc::c_ushort,
+        port_high: libc::c_uint,
+        iomap_base: libc::c_ulong,
+    }
+
+    /// Request low latency mode for the serial port with the given file descriptor.
+    ///
+    /// Returns whether or not the driver reports the mode as active afterwards.
+    pub fn enable(fd: RawFd) -> io::Result<bool> {
+        let mut serial = get(fd)?;
+        if serial.flags & ASYNC_LOW_LATENCY == 0 {
+            serial.flags |= ASYNC_LOW_LATENCY;
+            if unsafe { libc::ioctl(fd, TIOCSSERIAL as _, &serial as *const SerialStruct) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Read the flags back as some drivers silently ignore the request.
+            serial = get(fd)?;
+        }
+        Ok(serial.flags & ASYNC_LOW_LATENCY != 0)
+    }
+
+    fn get(fd: RawFd) -> io::Result<SerialStruct> {
+        let mut serial: SerialStruct = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(fd, TIOCGSERIAL as _, &mut serial as *mut SerialStruct) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(serial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::{SerialPortType, UsbPortInfo};
+
+    fn usb_port(name: &str, serial_number: Option<&str>) -> SerialPortInfo {
+        let usb = UsbPortInfo {
+            vid: 0x0403,
+            pid: 0x6001,
+            serial_number: serial_number.map(|s| s.to_string()),
+            manufacturer: None,
+            product: None,
+        };
+        SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: SerialPortType::UsbPort(usb),
+        }
+    }
+
+    #[test]
+    fn usb_selection_survives_replug() {
+        let selection = PortSelection::for_port(&usb_port("/dev/ttyUSB0", Some("A1")));
+        let expected = PortSelection::Usb {
+            vid: 0x0403,
+            pid: 0x6001,
+            serial_number: Some("A1".to_string()),
+        };
+        assert_eq!(selection, expected);
+        assert!(selection.matches(&usb_port("/dev/ttyUSB1", Some("A1"))));
+    }
+
+    #[test]
+    fn usb_selection_rejects_other_serial_number() {
+        let selection = PortSelection::for_port(&usb_port("/dev/ttyUSB0", Some("A1")));
+        assert!(!selection.matches(&usb_port("/dev/ttyUSB0", Some("B2"))));
+        assert!(!selection.matches(&usb_port("/dev/ttyUSB0", None)));
+    }
+
+    #[test]
+    fn port_without_serial_number_is_selected_by_name() {
+        let selection = PortSelection::for_port(&usb_port("/dev/ttyUSB0", None));
+        assert_eq!(selection, PortSelection::Name("/dev/ttyUSB0".to_string()));
+        assert!(selection.matches(&usb_port("/dev/ttyUSB0", None)));
+        assert!(!selection.matches(&usb_port("/dev/ttyUSB1", None)));
+    }
+
+    #[test]
+    fn first_usb_skips_other_ports() {
+        let pci = SerialPortInfo {
+            port_name: "/dev/ttyS0".to_string(),
+            port_type: SerialPortType::PciPort,
+        };
+        assert!(!PortSelection::FirstUsb.matches(&pci));
+        assert!(PortSelection::FirstUsb.matches(&usb_port("/dev/ttyUSB0", None)));
+    }
 }
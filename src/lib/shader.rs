@@ -0,0 +1,201 @@
+//! Runtime compilation of GLSL shaders to SPIR-V along with watching for their modification.
+//!
+//! This allows for tweaking the visualisation's shaders while it is running, without the need to
+//! manually re-compile the `*.spv` files or rebuild the software.
+
+use naga::back::spv;
+use naga::front::glsl;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// The interval at which the watcher checks for modified shaders.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The pipeline stage of a shader, determined by its file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// A `.vert` shader.
+    Vertex,
+    /// A `.frag` shader.
+    Fragment,
+}
+
+/// Describes why a shader failed to compile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    /// The name of the shader file.
+    pub name: String,
+    /// A description of each error, including the line and column where known.
+    pub message: String,
+}
+
+/// Watches a set of shader files for modifications by periodically polling their modification
+/// times.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    modified: Vec<Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl Stage {
+    /// Determine the stage of the shader at the given path from its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => Some(Stage::Vertex),
+            Some("frag") => Some(Stage::Fragment),
+            _ => None,
+        }
+    }
+
+    fn to_naga(self) -> naga::ShaderStage {
+        match self {
+            Stage::Vertex => naga::ShaderStage::Vertex,
+            Stage::Fragment => naga::ShaderStage::Fragment,
+        }
+    }
+}
+
+impl Watcher {
+    /// Watch the files at the given paths.
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let modified = paths.iter().map(|path| modified(path)).collect();
+        let last_poll = Instant::now();
+        Watcher {
+            paths,
+            modified,
+            last_poll,
+        }
+    }
+
+    /// Whether or not any of the files were modified since the last poll.
+    ///
+    /// The files are only checked once per `POLL_INTERVAL`, so this is cheap to call every frame.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.last_poll) < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = now;
+        let mut changed = false;
+        for (path, last_modified) in self.paths.iter().zip(&mut self.modified) {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Read and compile the GLSL shader at the given path, producing SPIR-V.
+///
+/// The stage is determined by the file extension.
+pub fn compile(path: &Path) -> Result<Vec<u8>, CompileError> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("{}", path.display()));
+    let error = |message: String| CompileError {
+        name: name.clone(),
+        message,
+    };
+    let stage = Stage::from_path(path)
+        .ok_or_else(|| error("unknown shader stage, expected `.vert` or `.frag`".to_string()))?;
+    let source = std::fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+    compile_source(&source, stage).map_err(error)
+}
+
+/// Compile the given GLSL source for the given stage, producing SPIR-V.
+///
+/// Upon failure, returns a description of each error.
+pub fn compile_source(source: &str, stage: Stage) -> Result<Vec<u8>, String> {
+    let module = parse(source, stage)?;
+    write_spirv(&module)
+}
+
+/// Parse the given GLSL source for the given stage, allowing for inspection of its interface.
+pub fn parse(source: &str, stage: Stage) -> Result<naga::Module, String> {
+    let options = glsl::Options::from(stage.to_naga());
+    glsl::Frontend::default()
+        .parse(&options, source)
+        .map_err(|errs| {
+            let messages: Vec<_> = errs
+                .iter()
+                .map(|err| {
+                    let loc = err.meta.location(source);
+                    format!("{}:{}: {}", loc.line_number, loc.line_position, err.kind)
+                })
+                .collect();
+            messages.join("\n")
+        })
+}
+
+/// Validate the given module and produce its SPIR-V.
+pub fn write_spirv(module: &naga::Module) -> Result<Vec<u8>, String> {
+    let info = Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(module)
+        .map_err(|err| format!("validation failed: {}", err.into_inner()))?;
+    let options = spv::Options {
+        lang_version: (1, 0),
+        flags: spv::WriterFlags::empty(),
+        ..Default::default()
+    };
+    let words = spv::write_vec(module, &info, &options, None).map_err(|err| err.to_string())?;
+    let bytes = words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+    Ok(bytes)
+}
+
+// The modification time of the file at the given path if it can be read.
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glsl_path() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src").join("lib").join("glsl")
+    }
+
+    #[test]
+    fn compile_vis_shaders() {
+        let names = [
+            "shader.vert",
+            "shader.frag",
+            "decay_shader.frag",
+            "decay_fade.frag",
+            "post.vert",
+            "crt.frag",
+        ];
+        for name in &names {
+            let spirv = compile(&glsl_path().join(name)).unwrap();
+            // The SPIR-V magic number.
+            assert_eq!(&spirv[..4], &0x0723_0203u32.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn errors_describe_location() {
+        let source = "#version 450\n\nvoid main() {\n    undeclared = 1.0;\n}\n";
+        let message = compile_source(source, Stage::Fragment).unwrap_err();
+        assert!(message.starts_with("4:"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_stage() {
+        let err = compile(&glsl_path().join("vert.spv")).unwrap_err();
+        assert_eq!(err.name, "vert.spv");
+    }
+}
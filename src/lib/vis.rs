@@ -1,23 +1,40 @@
 //! Items related to the visualisation including vulkan graphics and character sheet logic.
 
-use crate::conf::Config;
+use crate::conf::{self, Config};
+use crate::decode::{ALL_BUFFERS, DATA_BUFFERS_PER_FRAME, DATA_PER_BUFFER};
+use crate::post;
+use crate::shader;
 use nannou::image;
 use nannou::prelude::*;
-use std::cell::RefCell;
+use std::cell::{Cell, Ref, RefCell};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 const CHAR_SHEET_FILE_NAME: &str = "PetASCII_Combined.png";
-const CHAR_SHEET_ROWS: u8 = 32;
-const CHAR_SHEET_COLS: u8 = 16;
-const CHARS_PER_LINE: u8 = 80;
-const DATA_LINES: u8 = 25;
-const BLANK_LINES: u8 = 2;
-const TOTAL_LINES: u8 = DATA_LINES + BLANK_LINES;
+const VERTEX_SHADER_FILE_NAME: &str = "shader.vert";
+const FRAGMENT_SHADER_FILE_NAME: &str = "shader.frag";
+const DECAY_FRAGMENT_SHADER_FILE_NAME: &str = "decay_shader.frag";
+const DECAY_FADE_FRAGMENT_SHADER_FILE_NAME: &str = "decay_fade.frag";
+const FULLSCREEN_VERTEX_SHADER_FILE_NAME: &str = "post.vert";
+const CRT_FRAGMENT_SHADER_FILE_NAME: &str = "crt.frag";
 const GRAPHICS_MODE_ROW_OFFSET: u8 = 0;
 const TEXT_MODE_ROW_OFFSET: u8 = 16;
 const VERTEX_COUNT: usize = 6;
-const DECAY_IMAGE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+const INSTANCE_COUNT: usize = TOTAL_LINES as usize * CHARS_PER_LINE as usize;
+// The screen buffer holds the mode as a `u32` followed by the raw frame data.
+const SCREEN_MODE_SIZE: wgpu::BufferAddress = std::mem::size_of::<u32>() as _;
+const SCREEN_BUFFER_SIZE: wgpu::BufferAddress =
+    SCREEN_MODE_SIZE + CBM_8032_FRAME_DATA_LEN as wgpu::BufferAddress;
+// The decay image holds the brightness of the fast and slow components of the phosphor.
+const DECAY_IMAGE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
 
+pub const CHAR_SHEET_ROWS: u8 = 32;
+pub const CHAR_SHEET_COLS: u8 = 16;
+pub const CHARS_PER_LINE: u8 = 80;
+pub const DATA_LINES: u8 = 25;
+/// Blank lines drawn around the data, half above and half below.
+pub const BLANK_LINES: u8 = 2;
+pub const TOTAL_LINES: u8 = DATA_LINES + BLANK_LINES;
 pub const CBM_8032_FRAME_DATA_LEN: usize = CHARS_PER_LINE as usize * DATA_LINES as usize;
 
 /// Items related to the visualisation.
@@ -25,16 +42,28 @@ pub struct Vis {
     _char_sheet: wgpu::Texture,
     char_sheet_view: wgpu::TextureView,
     graphics: RefCell<Graphics>,
+    screen: RefCell<Screen>,
+    glsl_path: PathBuf,
+    shaders: RefCell<Shaders>,
+    shader_watcher: RefCell<shader::Watcher>,
+    shader_error: RefCell<Option<shader::CompileError>>,
+    post: RefCell<post::Chain>,
+    // The time of the previous view, from which the phosphor decay is determined.
+    last_view: Cell<Option<Instant>>,
 }
 
 /// The frame type representing all data necessary for displaying a single frame.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Cbm8032Frame {
     pub mode: Cbm8032FrameMode,
     pub data: Box<Cbm8032FrameData>,
+    /// The buffers whose data was not received as a part of this frame and was instead carried
+    /// over from an earlier frame.
+    pub stale: BufferMask,
 }
 
 /// The two modes in which
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Cbm8032FrameMode {
     Graphics,
     Text,
@@ -43,13 +72,38 @@ pub enum Cbm8032FrameMode {
 /// The type used to represent the CBM 8032 graphical data.
 pub type Cbm8032FrameData = [u8; CBM_8032_FRAME_DATA_LEN];
 
+/// A mask with one bit per serial buffer of frame data, where the least significant bit describes
+/// the first `DATA_PER_BUFFER` bytes.
+pub type BufferMask = u64;
+
+/// The changes required to bring a frame up to date with the next, containing only the data of
+/// the buffers that changed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameDelta {
+    pub mode: Cbm8032FrameMode,
+    pub stale: BufferMask,
+    /// The buffers whose data changed.
+    pub dirty: BufferMask,
+    /// The data of each dirty buffer, in ascending order of buffer index.
+    pub data: Vec<u8>,
+}
+
 // The vulkan renderpass, pipeline and related items.
 struct Graphics {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
     uniform_buffer: wgpu::Buffer,
+    // The uniforms most recently written to the uniform buffer.
+    uniforms: Uniforms,
     vertex_buffer: wgpu::Buffer,
+    // The mode and raw screen bytes from which the vertex shader derives each character.
+    screen_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
     decay: Decay,
+    // Present while the CRT emulation mode is enabled.
+    crt: Option<Crt>,
+    // The sample count of the pipeline's render target.
+    msaa_samples: u32,
     _sampler: wgpu::Sampler,
 }
 
@@ -57,14 +111,65 @@ struct Decay {
     texture: wgpu::Texture,
     texture_view: wgpu::TextureView,
     bind_group: wgpu::BindGroup,
+    // Multiplies the decay image by the decay factors.
+    fade_pipeline: wgpu::RenderPipeline,
+    // Excites the phosphor wherever the beam strikes.
+    pipeline: wgpu::RenderPipeline,
+}
+
+// The CRT emulation pass, reading the main render from its own texture.
+struct Crt {
+    _texture: wgpu::Texture,
+    texture_view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
     pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    // The uniforms most recently written to the uniform buffer.
+    uniforms: CrtUniforms,
+    // The sample count of the pass's render target.
+    msaa_samples: u32,
+    _sampler: wgpu::Sampler,
+}
+
+// The SPIR-V for each of the shaders.
+struct Shaders {
+    vert: Vec<u8>,
+    frag: Vec<u8>,
+    decay_frag: Vec<u8>,
+    decay_fade_frag: Vec<u8>,
+    fullscreen_vert: Vec<u8>,
+    crt_frag: Vec<u8>,
+}
+
+// Tracks the state of the screen buffer, which is updated in place for the buffers that changed.
+struct Screen {
+    // Buffers that changed since the previous view.
+    dirty: BufferMask,
+    // The mode and the stale buffers shown in reverse video during the previous view.
+    mode: Option<Cbm8032FrameMode>,
+    shown_stale: BufferMask,
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 struct Uniforms {
     colouration: [f32; 4],
-    sustain: f32,
+    decay_factors: [f32; 2],
+    decay_weights: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct CrtUniforms {
+    resolution: [f32; 2],
+    curvature: f32,
+    scanlines: f32,
+    mask_strength: f32,
+    bloom: f32,
+    vignette: f32,
+    convergence: f32,
+    mask: u32,
+    _pad: [u32; 3],
 }
 
 // Vertex type used for GPU geometry.
@@ -75,24 +180,20 @@ struct Vertex {
     tex_coords: [f32; 2],
 }
 
-// Instance is the vertex type that describes the unique data per instance.
-#[derive(Clone, Copy, Debug, Default)]
-#[repr(C)]
-struct Instance {
-    position_offset: [f32; 2],
-    tex_coords_offset: [f32; 2],
-}
-
 impl Cbm8032Frame {
-    const BLANK_BYTE: u8 = 32;
+    pub const BLANK_BYTE: u8 = 32;
     const BLANK_DATA: Cbm8032FrameData = [Self::BLANK_BYTE; CBM_8032_FRAME_DATA_LEN];
 
     /// Construct a new `Cbm8032Frame` from the given mode and data.
     pub fn new(mode: Cbm8032FrameMode, data: Box<Cbm8032FrameData>) -> Self {
-        Cbm8032Frame {
-            mode,
-            data,
-        }
+        let stale = 0;
+        Cbm8032Frame { mode, data, stale }
+    }
+
+    /// Whether or not the character at the given index within the frame data is stale.
+    pub fn is_stale(&self, char_index: usize) -> bool {
+        let buffer_index = char_index / DATA_PER_BUFFER as usize;
+        self.stale & (1 << buffer_index) != 0
     }
 
     /// Create a frame containing blank data in graphics mode.
@@ -116,6 +217,72 @@ impl Cbm8032Frame {
     }
 }
 
+impl FrameDelta {
+    /// Describe the given frame, including only the data of the `dirty` buffers.
+    pub fn new(frame: &Cbm8032Frame, dirty: BufferMask) -> Self {
+        let data = buffer_indices(dirty)
+            .flat_map(|ix| buffer_slice(&frame.data, ix).iter().cloned())
+            .collect();
+        FrameDelta {
+            mode: frame.mode,
+            stale: frame.stale,
+            dirty,
+            data,
+        }
+    }
+
+    /// The data for the buffer at the given index if it is dirty.
+    pub fn buffer(&self, buffer_index: usize) -> Option<&[u8]> {
+        if self.dirty & (1 << buffer_index) == 0 {
+            return None;
+        }
+        let preceding = (self.dirty & ((1 << buffer_index) - 1)).count_ones() as usize;
+        let start = preceding * DATA_PER_BUFFER as usize;
+        self.data.get(start..start + DATA_PER_BUFFER as usize)
+    }
+
+    /// Apply the changes to the given frame.
+    pub fn apply(&self, frame: &mut Cbm8032Frame) {
+        frame.mode = self.mode;
+        frame.stale = self.stale;
+        let chunks = self.data.chunks(DATA_PER_BUFFER as usize);
+        for (ix, chunk) in buffer_indices(self.dirty).zip(chunks) {
+            buffer_slice_mut(&mut frame.data, ix).copy_from_slice(chunk);
+        }
+    }
+
+    /// Combine this delta with the one that followed it, producing a single delta with the same
+    /// result.
+    pub fn merge(&mut self, next: FrameDelta) {
+        let dirty = self.dirty | next.dirty;
+        let mut data = Vec::with_capacity(dirty.count_ones() as usize * DATA_PER_BUFFER as usize);
+        for ix in buffer_indices(dirty) {
+            let buffer = next.buffer(ix).or_else(|| self.buffer(ix)).unwrap_or_default();
+            data.extend_from_slice(buffer);
+        }
+        self.mode = next.mode;
+        self.stale = next.stale;
+        self.dirty = dirty;
+        self.data = data;
+    }
+}
+
+/// The indices of the buffers within the mask in ascending order.
+pub fn buffer_indices(mask: BufferMask) -> impl Iterator<Item = usize> {
+    (0..DATA_BUFFERS_PER_FRAME as usize).filter(move |ix| mask & (1 << ix) != 0)
+}
+
+// The slice of the frame data described by the buffer at the given index.
+fn buffer_slice(data: &Cbm8032FrameData, buffer_index: usize) -> &[u8] {
+    let start = buffer_index * DATA_PER_BUFFER as usize;
+    &data[start..start + DATA_PER_BUFFER as usize]
+}
+
+fn buffer_slice_mut(data: &mut Cbm8032FrameData, buffer_index: usize) -> &mut [u8] {
+    let start = buffer_index * DATA_PER_BUFFER as usize;
+    &mut data[start..start + DATA_PER_BUFFER as usize]
+}
+
 /// Randomise the given frame data.
 pub fn randomise_frame_data(data: &mut Cbm8032FrameData) {
     for b in data.iter_mut() {
@@ -129,11 +296,90 @@ pub fn init(assets_path: &Path, window: &nannou::window::Window, msaa_samples: u
     let char_sheet_view = char_sheet.view().build();
     let device = window.swap_chain_device();
     let (w, h) = window.inner_size_pixels();
-    let graphics = RefCell::new(init_graphics(device, [w, h], msaa_samples, &char_sheet_view));
+
+    // Compile the GLSL shaders, falling back to the bundled SPIR-V if they fail to compile.
+    let glsl_path = glsl_path(assets_path);
+    let shader_watcher = RefCell::new(shader::Watcher::new(shader_paths(&glsl_path)));
+    let (shaders, shader_error) = match Shaders::compile(&glsl_path) {
+        Ok(shaders) => (shaders, None),
+        Err(err) => {
+            eprintln!("failed to compile shaders, using bundled SPIR-V: {}", err);
+            (Shaders::bundled(), Some(err))
+        }
+    };
+
+    let crt_msaa_samples = None;
+    let graphics = init_graphics(
+        device,
+        [w, h],
+        msaa_samples,
+        crt_msaa_samples,
+        &char_sheet_view,
+        &shaders,
+    );
+    let graphics = RefCell::new(graphics);
+    let screen = RefCell::new(init_screen());
     Vis {
         _char_sheet: char_sheet,
         char_sheet_view,
         graphics,
+        screen,
+        glsl_path,
+        shaders: RefCell::new(shaders),
+        shader_watcher,
+        shader_error: RefCell::new(shader_error),
+        post: RefCell::new(post::Chain::new(post::shaders_path(assets_path))),
+        last_view: Cell::new(None),
+    }
+}
+
+impl Vis {
+    /// Mark the given buffers of the frame as changed so that they are updated on the next view.
+    pub fn mark_dirty(&self, buffers: BufferMask) {
+        self.screen.borrow_mut().dirty |= buffers;
+    }
+
+    /// The directory containing the GLSL shaders that are watched for changes.
+    pub fn glsl_path(&self) -> &Path {
+        &self.glsl_path
+    }
+
+    /// The error produced by the most recent attempt to compile the shaders, if it failed.
+    ///
+    /// While this is `Some`, the previously compiled shaders remain in use.
+    pub fn shader_error(&self) -> Option<shader::CompileError> {
+        self.shader_error.borrow().clone()
+    }
+
+    /// The chain of post-processing passes applied to the visualisation.
+    pub fn post(&self) -> Ref<post::Chain> {
+        self.post.borrow()
+    }
+}
+
+impl Shaders {
+    // Compile each of the GLSL shaders within the given directory.
+    fn compile(glsl_path: &Path) -> Result<Self, shader::CompileError> {
+        Ok(Shaders {
+            vert: shader::compile(&glsl_path.join(VERTEX_SHADER_FILE_NAME))?,
+            frag: shader::compile(&glsl_path.join(FRAGMENT_SHADER_FILE_NAME))?,
+            decay_frag: shader::compile(&glsl_path.join(DECAY_FRAGMENT_SHADER_FILE_NAME))?,
+            decay_fade_frag: shader::compile(&glsl_path.join(DECAY_FADE_FRAGMENT_SHADER_FILE_NAME))?,
+            fullscreen_vert: shader::compile(&glsl_path.join(FULLSCREEN_VERTEX_SHADER_FILE_NAME))?,
+            crt_frag: shader::compile(&glsl_path.join(CRT_FRAGMENT_SHADER_FILE_NAME))?,
+        })
+    }
+
+    // The pre-compiled SPIR-V bundled with the executable.
+    fn bundled() -> Self {
+        Shaders {
+            vert: include_bytes!("glsl/vert.spv").to_vec(),
+            frag: include_bytes!("glsl/frag.spv").to_vec(),
+            decay_frag: include_bytes!("glsl/decay_frag.spv").to_vec(),
+            decay_fade_frag: include_bytes!("glsl/decay_fade_frag.spv").to_vec(),
+            fullscreen_vert: include_bytes!("glsl/post_vert.spv").to_vec(),
+            crt_frag: include_bytes!("glsl/crt_frag.spv").to_vec(),
+        }
     }
 }
 
@@ -142,53 +388,113 @@ pub fn view(config: &Config, vis: &Vis, cbm_frame: &Cbm8032Frame, frame: Frame)
     let device_queue_pair = frame.device_queue_pair();
     let device = device_queue_pair.device();
 
-    // Update the uniforms.
-    let hsv = config.colouration.hsv();
-    let lin_srgb: LinSrgb = hsv.into();
-    let colouration = [lin_srgb.red, lin_srgb.green, lin_srgb.blue, config.colouration.alpha];
-    let sustain = config.sustain;
-    let uniforms = Uniforms { colouration, sustain };
-    let uniforms_size = std::mem::size_of::<Uniforms>() as wgpu::BufferAddress;
-    let uniforms_bytes = uniforms_as_bytes(&uniforms);
-    let usage = wgpu::BufferUsage::COPY_SRC;
-    let new_uniform_buffer = device.create_buffer_with_data(uniforms_bytes, usage);
-
-    // Create the instance data buffer.
-    fn blank_line_bytes() -> impl Iterator<Item = u8> {
-        (0..CHARS_PER_LINE).map(|_| Cbm8032Frame::BLANK_BYTE)
-    }
-    let all_bytes = blank_line_bytes()
-        .chain(cbm_frame.data.iter().cloned())
-        .chain(blank_line_bytes());
-    let instances: Vec<Instance> = all_bytes
-        .enumerate()
-        .map(|(ix, byte)| {
-            let col_row = byte_to_char_sheet_col_row(byte, &cbm_frame.mode);
-            let tex_coords_offset = char_sheet_col_row_to_tex_coords_offset(col_row);
-            let position_offset = serial_char_index_to_position_offset(ix as _);
-            Instance {
-                position_offset,
-                tex_coords_offset,
+    // If any of the shaders were modified, recompile them. Upon failure, the error is shown via
+    // the GUI and the previous shaders remain in use.
+    let mut shaders_changed = false;
+    if vis.shader_watcher.borrow_mut().poll() {
+        match Shaders::compile(&vis.glsl_path) {
+            Ok(shaders) => {
+                vis.shaders.replace(shaders);
+                vis.shader_error.replace(None);
+                shaders_changed = true;
             }
-        })
-        .collect();
-    let instances_bytes = instances_as_bytes(&instances[..]);
-    let usage = wgpu::BufferUsage::VERTEX;
-    let instance_buffer = device.create_buffer_with_data(instances_bytes, usage);
+            Err(err) => {
+                eprintln!("failed to compile shaders: {}", err);
+                vis.shader_error.replace(Some(err));
+            }
+        }
+    }
 
-    // If the window changed sizes, we need to recreate the decay buffer and in turn, the whole
-    // graphics pipeline.
+    // Load the enabled post-processing passes. While any are enabled, the visualisation is
+    // rendered to the chain's single-sampled input texture rather than to the frame.
     let frame_wh = frame.texture_size();
     let frame_msaa_samples = frame.texture_msaa_samples();
-    if vis.graphics.borrow().decay.texture_view.size() != frame.texture_size() {
-        let new_graphics = init_graphics(device, frame_wh, frame_msaa_samples, &vis.char_sheet_view);
-        vis.graphics.replace(new_graphics);
+    let mut post = vis.post.borrow_mut();
+    post.update_shaders(&config.post_processing);
+    post.prepare(device, frame_wh, frame_msaa_samples);
+    let output_msaa_samples = if post.is_active() { 1 } else { frame_msaa_samples };
+
+    // Similarly, while the CRT emulation mode is enabled the visualisation is rendered to the CRT
+    // pass's texture, which in turn renders to the output.
+    let crt_msaa_samples = if config.crt.enabled {
+        Some(output_msaa_samples)
+    } else {
+        None
+    };
+    let msaa_samples = if config.crt.enabled { 1 } else { output_msaa_samples };
+
+    // If the window changed sizes, we need to recreate the decay buffer and in turn, the whole
+    // graphics pipeline. The same goes for when the shaders or the render targets change. The new
+    // screen buffer must then be written in full.
+    let mut graphics = vis.graphics.borrow_mut();
+    let mut screen = vis.screen.borrow_mut();
+    if graphics.decay.texture_view.size() != frame_wh
+        || graphics.msaa_samples != msaa_samples
+        || graphics.crt.as_ref().map(|crt| crt.msaa_samples) != crt_msaa_samples
+        || shaders_changed
+    {
+        let char_sheet = &vis.char_sheet_view;
+        let shaders = vis.shaders.borrow();
+        *graphics = init_graphics(
+            device,
+            frame_wh,
+            msaa_samples,
+            crt_msaa_samples,
+            char_sheet,
+            &shaders,
+        );
+        *screen = init_screen();
     }
 
-    // Encode the new buffer copies and the render pass.
+    // Encode copies for the uniforms and screen data that changed.
     let mut encoder = frame.command_encoder();
-    let graphics = vis.graphics.borrow();
-    encoder.copy_buffer_to_buffer(&new_uniform_buffer, 0, &graphics.uniform_buffer, 0, uniforms_size);
+    let hsv = config.colouration.hsv();
+    let lin_srgb: LinSrgb = hsv.into();
+    let colouration = [lin_srgb.red, lin_srgb.green, lin_srgb.blue, 1.0];
+
+    // Decay the phosphor by the real time elapsed since the previous view, so that persistence
+    // does not depend on the frame rate.
+    let now = Instant::now();
+    let elapsed = vis
+        .last_view
+        .replace(Some(now))
+        .map(|last| now.duration_since(last))
+        .unwrap_or_default();
+    let decay = config.phosphor.decay();
+    let decay_factors = decay.factors(elapsed);
+    let decay_weights = decay.weights();
+    let uniforms = Uniforms {
+        colouration,
+        decay_factors,
+        decay_weights,
+    };
+    if uniforms != graphics.uniforms {
+        let uniforms_size = std::mem::size_of::<Uniforms>() as wgpu::BufferAddress;
+        let uniforms_bytes = uniforms_as_bytes(&uniforms);
+        let usage = wgpu::BufferUsage::COPY_SRC;
+        let new_uniform_buffer = device.create_buffer_with_data(uniforms_bytes, usage);
+        encoder.copy_buffer_to_buffer(&new_uniform_buffer, 0, &graphics.uniform_buffer, 0, uniforms_size);
+        graphics.uniforms = uniforms;
+    }
+    if let Some(crt) = graphics.crt.as_mut() {
+        let uniforms = crt_uniforms(&config.crt, frame_wh);
+        if uniforms != crt.uniforms {
+            let uniforms_size = std::mem::size_of::<CrtUniforms>() as wgpu::BufferAddress;
+            let uniforms_bytes = crt_uniforms_as_bytes(&uniforms);
+            let usage = wgpu::BufferUsage::COPY_SRC;
+            let new_uniform_buffer = device.create_buffer_with_data(uniforms_bytes, usage);
+            encoder.copy_buffer_to_buffer(&new_uniform_buffer, 0, &crt.uniform_buffer, 0, uniforms_size);
+            crt.uniforms = uniforms;
+        }
+    }
+    update_screen(
+        device,
+        &mut encoder,
+        &mut screen,
+        &graphics.screen_buffer,
+        cbm_frame,
+        config.show_stale_buffers,
+    );
 
     // Render pass for rendering to the decay image.
     {
@@ -203,31 +509,54 @@ pub fn view(config: &Config, vis: &Vis, cbm_frame: &Cbm8032Frame, frame: Frame)
             })
             .begin(&mut encoder);
         render_pass.set_bind_group(0, &decay.bind_group, &[]);
+        render_pass.set_bind_group(1, &graphics.screen_bind_group, &[]);
+        render_pass.set_pipeline(&decay.fade_pipeline);
+        render_pass.draw(0..3, 0..1);
         render_pass.set_pipeline(&decay.pipeline);
         render_pass.set_vertex_buffer(0, &graphics.vertex_buffer, 0, 0);
-        render_pass.set_vertex_buffer(1, &instance_buffer, 0, 0);
         let vertex_range = 0..VERTEX_COUNT as u32;
-        let instance_range = 0..instances.len() as u32;
+        let instance_range = 0..INSTANCE_COUNT as u32;
         render_pass.draw(vertex_range, instance_range);
     }
 
-    // Render pass for rendering to the swapchain image.
+    // Render pass for rendering to the swapchain image, or to the input of the CRT pass or
+    // post-processing chain if either is active.
+    let output = post.input().unwrap_or_else(|| frame.texture_view());
     {
+        let target = match graphics.crt {
+            Some(ref crt) => &crt.texture_view,
+            None => output,
+        };
         let mut render_pass = wgpu::RenderPassBuilder::new()
-            .color_attachment(frame.texture_view(), |color| color)
+            .color_attachment(target, |color| color)
             .begin(&mut encoder);
         render_pass.set_bind_group(0, &graphics.bind_group, &[]);
+        render_pass.set_bind_group(1, &graphics.screen_bind_group, &[]);
         render_pass.set_pipeline(&graphics.pipeline);
         render_pass.set_vertex_buffer(0, &graphics.vertex_buffer, 0, 0);
-        render_pass.set_vertex_buffer(1, &instance_buffer, 0, 0);
         let vertex_range = 0..VERTEX_COUNT as u32;
-        let instance_range = 0..instances.len() as u32;
+        let instance_range = 0..INSTANCE_COUNT as u32;
         render_pass.draw(vertex_range, instance_range);
     }
+
+    // Render pass for emulating the CRT.
+    if let Some(ref crt) = graphics.crt {
+        let mut render_pass = wgpu::RenderPassBuilder::new()
+            .color_attachment(output, |color| color)
+            .begin(&mut encoder);
+        render_pass.set_bind_group(0, &crt.bind_group, &[]);
+        render_pass.set_pipeline(&crt.pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Apply each of the post-processing passes, the last of which writes to the swapchain image.
+    post.encode(device, &mut encoder, &config.post_processing, frame.texture_view());
 }
 
 /// Given a byte value from the serial data, return the column and row of the character within the
 /// `CHAR_SHEET` starting from the top left.
+///
+/// The vertex shader performs the same mapping on the GPU, so the two must be kept in sync.
 pub fn byte_to_char_sheet_col_row(byte: u8, mode: &Cbm8032FrameMode) -> [u8; 2] {
     let row_offset = match mode {
         Cbm8032FrameMode::Graphics => GRAPHICS_MODE_ROW_OFFSET,
@@ -248,6 +577,9 @@ pub fn char_sheet_col_row_to_tex_coords_offset([col, row]: [u8; 2]) -> [f32; 2]
 
 /// Given the index of a character within the serial data, produce the position offset for the
 /// character.
+///
+/// The vertex shader performs the same mapping on the GPU, where the index includes the leading
+/// blank line.
 pub fn serial_char_index_to_position_offset(char_index: u16) -> [f32; 2] {
     let col = char_index % CHARS_PER_LINE as u16;
     let row = char_index / CHARS_PER_LINE as u16;
@@ -256,10 +588,84 @@ pub fn serial_char_index_to_position_offset(char_index: u16) -> [f32; 2] {
     [x, y]
 }
 
+// The value describing the mode within the screen buffer.
+fn mode_to_u32(mode: &Cbm8032FrameMode) -> u32 {
+    match mode {
+        Cbm8032FrameMode::Graphics => 0,
+        Cbm8032FrameMode::Text => 1,
+    }
+}
+
+// The screen buffer state for a newly created screen buffer, requiring a full update.
+fn init_screen() -> Screen {
+    Screen {
+        dirty: ALL_BUFFERS,
+        mode: None,
+        shown_stale: 0,
+    }
+}
+
+// Copy the mode and the data of all buffers that changed since the previous view into the screen
+// buffer, using a single copy for each run of adjacent buffers.
+fn update_screen(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    screen: &mut Screen,
+    screen_buffer: &wgpu::Buffer,
+    cbm_frame: &Cbm8032Frame,
+    show_stale: bool,
+) {
+    let usage = wgpu::BufferUsage::COPY_SRC;
+    if screen.mode != Some(cbm_frame.mode) {
+        let mode_bytes = mode_to_u32(&cbm_frame.mode).to_ne_bytes();
+        let staging_buffer = device.create_buffer_with_data(&mode_bytes, usage);
+        encoder.copy_buffer_to_buffer(&staging_buffer, 0, screen_buffer, 0, SCREEN_MODE_SIZE);
+    }
+
+    // Stale characters are optionally shown in reverse video, which lives in the upper half of
+    // each mode's character set.
+    let shown_stale = if show_stale { cbm_frame.stale } else { 0 };
+    let dirty = screen.dirty | (screen.shown_stale ^ shown_stale);
+    for run in buffer_runs(dirty) {
+        let start = run.start * DATA_PER_BUFFER as usize;
+        let end = run.end * DATA_PER_BUFFER as usize;
+        let bytes: Vec<u8> = (start..end)
+            .map(|ix| {
+                let byte = cbm_frame.data[ix];
+                let buffer_index = ix / DATA_PER_BUFFER as usize;
+                if shown_stale & (1 << buffer_index) != 0 {
+                    byte ^ 0x80
+                } else {
+                    byte
+                }
+            })
+            .collect();
+        let staging_buffer = device.create_buffer_with_data(&bytes, usage);
+        let offset = SCREEN_MODE_SIZE + start as wgpu::BufferAddress;
+        let size = bytes.len() as wgpu::BufferAddress;
+        encoder.copy_buffer_to_buffer(&staging_buffer, 0, screen_buffer, offset, size);
+    }
+
+    screen.dirty = 0;
+    screen.mode = Some(cbm_frame.mode);
+    screen.shown_stale = shown_stale;
+}
+
+// The ranges of adjacent buffer indices within the mask in ascending order.
+fn buffer_runs(mask: BufferMask) -> Vec<std::ops::Range<usize>> {
+    let mut runs: Vec<std::ops::Range<usize>> = vec![];
+    for ix in buffer_indices(mask) {
+        match runs.last_mut() {
+            Some(run) if run.end == ix => run.end += 1,
+            _ => runs.push(ix..ix + 1),
+        }
+    }
+    runs
+}
+
 // Load the character sheet.
 fn load_char_sheet(assets_path: &Path, window: &nannou::window::Window) -> wgpu::Texture {
-    let images_path = images_path(assets_path);
-    let path = images_path.join(CHAR_SHEET_FILE_NAME);
+    let path = char_sheet_path(assets_path);
     let image = image::open(&path).expect("failed to open image");
     // Load the image as a texture.
     wgpu::Texture::from_image(window, &image)
@@ -270,16 +676,23 @@ fn init_graphics(
     device: &wgpu::Device,
     swap_chain_dims: [u32; 2],
     msaa_samples: u32,
+    crt_msaa_samples: Option<u32>,
     char_sheet: &wgpu::TextureView,
+    shaders: &Shaders,
 ) -> Graphics {
     // Load shader modules.
-    let vs_mod = wgpu::shader_from_spirv_bytes(device, include_bytes!("glsl/vert.spv"));
-    let fs_mod = wgpu::shader_from_spirv_bytes(device, include_bytes!("glsl/frag.spv"));
+    let vs_mod = wgpu::shader_from_spirv_bytes(device, &shaders.vert);
+    let fs_mod = wgpu::shader_from_spirv_bytes(device, &shaders.frag);
 
     // Initialise the uniform buffer.
     let colouration = [0.0; 4];
-    let sustain = 1.0;
-    let uniforms = Uniforms { colouration, sustain };
+    let decay_factors = [1.0; 2];
+    let decay_weights = [0.0; 2];
+    let uniforms = Uniforms {
+        colouration,
+        decay_factors,
+        decay_weights,
+    };
     let uniforms_bytes = uniforms_as_bytes(&uniforms);
     let usage = wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST;
     let uniform_buffer = device.create_buffer_with_data(uniforms_bytes, usage);
@@ -287,13 +700,21 @@ fn init_graphics(
     // For sampling the char sheet.
     let sampler = create_sampler(device);
 
+    // Initialise the screen buffer with a blank frame, later updated in place via copies.
+    let screen_buffer = create_screen_buffer(device);
+    let screen_bind_group_layout = create_screen_bind_group_layout(device);
+    let screen_bind_group =
+        create_screen_bind_group(device, &screen_bind_group_layout, &screen_buffer);
+
     let decay = init_decay(
         device,
         swap_chain_dims,
         &vs_mod,
+        shaders,
         char_sheet,
         &uniform_buffer,
         &sampler,
+        &screen_bind_group_layout,
     );
 
     let bind_group_layout = create_bind_group_layout(
@@ -309,7 +730,8 @@ fn init_graphics(
         &decay.texture_view,
         &sampler,
     );
-    let pipeline_layout = create_pipeline_layout(device, &bind_group_layout);
+    let bind_group_layouts = [&bind_group_layout, &screen_bind_group_layout];
+    let pipeline_layout = create_pipeline_layout(device, &bind_group_layouts);
     let pipeline = create_pipeline(
         device,
         &pipeline_layout,
@@ -321,25 +743,116 @@ fn init_graphics(
 
     let vertex_buffer = create_vertex_buffer(device.clone());
 
+    let crt = crt_msaa_samples
+        .map(|crt_msaa_samples| init_crt(device, swap_chain_dims, crt_msaa_samples, shaders));
+
     Graphics {
         pipeline,
         bind_group,
         vertex_buffer,
+        screen_buffer,
+        screen_bind_group,
         uniform_buffer,
+        uniforms,
         decay,
+        crt,
+        msaa_samples,
         _sampler: sampler,
     }
 }
 
+fn init_crt(
+    device: &wgpu::Device,
+    swap_chain_dims: [u32; 2],
+    msaa_samples: u32,
+    shaders: &Shaders,
+) -> Crt {
+    let vs_mod = wgpu::shader_from_spirv_bytes(device, &shaders.fullscreen_vert);
+    let fs_mod = wgpu::shader_from_spirv_bytes(device, &shaders.crt_frag);
+    let texture = wgpu::TextureBuilder::new()
+        .size(swap_chain_dims)
+        .usage(wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED)
+        .format(Frame::TEXTURE_FORMAT)
+        .build(device);
+    let texture_view = texture.view().build();
+
+    // The CRT samples between pixels when bending the screen, so filter linearly.
+    let sampler = wgpu::SamplerBuilder::new()
+        .mag_filter(wgpu::FilterMode::Linear)
+        .min_filter(wgpu::FilterMode::Linear)
+        .build(device);
+
+    let uniforms = crt_uniforms(&Default::default(), swap_chain_dims);
+    let uniforms_bytes = crt_uniforms_as_bytes(&uniforms);
+    let usage = wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST;
+    let uniform_buffer = device.create_buffer_with_data(uniforms_bytes, usage);
+
+    let bind_group_layout = wgpu::BindGroupLayoutBuilder::new()
+        .sampled_texture(
+            wgpu::ShaderStage::FRAGMENT,
+            false,
+            wgpu::TextureViewDimension::D2,
+            texture.component_type(),
+        )
+        .sampler(wgpu::ShaderStage::FRAGMENT)
+        .uniform_buffer(wgpu::ShaderStage::FRAGMENT, false)
+        .build(device);
+    let bind_group = wgpu::BindGroupBuilder::new()
+        .texture_view(&texture_view)
+        .sampler(&sampler)
+        .buffer::<CrtUniforms>(&uniform_buffer, 0..1)
+        .build(device, &bind_group_layout);
+    let pipeline_layout = create_pipeline_layout(device, &[&bind_group_layout]);
+    let pipeline = wgpu::RenderPipelineBuilder::from_layout(&pipeline_layout, &vs_mod)
+        .fragment_shader(&fs_mod)
+        .color_format(Frame::TEXTURE_FORMAT)
+        .sample_count(msaa_samples)
+        .build(device);
+
+    Crt {
+        _texture: texture,
+        texture_view,
+        bind_group,
+        pipeline,
+        uniform_buffer,
+        uniforms,
+        msaa_samples,
+        _sampler: sampler,
+    }
+}
+
+// The uniforms for the CRT pass from the given config.
+fn crt_uniforms(crt: &conf::Crt, [w, h]: [u32; 2]) -> CrtUniforms {
+    let mask = match crt.mask {
+        conf::CrtMask::ShadowMask => 0,
+        conf::CrtMask::ApertureGrille => 1,
+    };
+    CrtUniforms {
+        resolution: [w as f32, h as f32],
+        curvature: crt.curvature,
+        scanlines: crt.scanlines,
+        mask_strength: crt.mask_strength,
+        bloom: crt.bloom,
+        vignette: crt.vignette,
+        convergence: crt.convergence,
+        mask,
+        _pad: [0; 3],
+    }
+}
+
 fn init_decay(
     device: &wgpu::Device,
     swap_chain_dims: [u32; 2],
     vs_mod: &wgpu::ShaderModule,
+    shaders: &Shaders,
     char_sheet: &wgpu::TextureView,
     uniform_buffer: &wgpu::Buffer,
     sampler: &wgpu::Sampler,
+    screen_bind_group_layout: &wgpu::BindGroupLayout,
 ) -> Decay {
-    let fs_mod = wgpu::shader_from_spirv_bytes(device, include_bytes!("glsl/decay_frag.spv"));
+    let fs_mod = wgpu::shader_from_spirv_bytes(device, &shaders.decay_frag);
+    let fade_vs_mod = wgpu::shader_from_spirv_bytes(device, &shaders.fullscreen_vert);
+    let fade_fs_mod = wgpu::shader_from_spirv_bytes(device, &shaders.decay_fade_frag);
     let texture = wgpu::TextureBuilder::new()
         .size(swap_chain_dims)
         .usage(wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED)
@@ -348,20 +861,42 @@ fn init_decay(
     let texture_view = texture.view().build();
     let bind_group_layout = create_decay_bind_group_layout(device, char_sheet.component_type());
     let bind_group = create_decay_bind_group(device, &bind_group_layout, &uniform_buffer, char_sheet, &sampler);
-    let pipeline_layout = create_pipeline_layout(device, &bind_group_layout);
-    let msaa_samples = 1;
-    let pipeline = create_pipeline(
-        device,
-        &pipeline_layout,
-        &vs_mod,
-        &fs_mod,
-        texture_view.format(),
-        msaa_samples,
-    );
+    let bind_group_layouts = [&bind_group_layout, screen_bind_group_layout];
+    let pipeline_layout = create_pipeline_layout(device, &bind_group_layouts);
+
+    // Multiply the existing decay image by the output of the fade shader.
+    let fade_blend = wgpu::BlendDescriptor {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::SrcColor,
+        operation: wgpu::BlendOperation::Add,
+    };
+    let fade_pipeline = wgpu::RenderPipelineBuilder::from_layout(&pipeline_layout, &fade_vs_mod)
+        .fragment_shader(&fade_fs_mod)
+        .color_format(texture_view.format())
+        .color_blend(fade_blend)
+        .build(device);
+
+    // Re-excite the phosphor where it is brighter than what remains of its decay.
+    let excite_blend = wgpu::BlendDescriptor {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Max,
+    };
+    let pipeline = wgpu::RenderPipelineBuilder::from_layout(&pipeline_layout, vs_mod)
+        .fragment_shader(&fs_mod)
+        .color_format(texture_view.format())
+        .color_blend(excite_blend)
+        .add_vertex_buffer::<Vertex>(&wgpu::vertex_attr_array![
+            0 => Float2,
+            1 => Float2
+        ])
+        .build(device);
+
     Decay {
         texture,
         texture_view,
         bind_group,
+        fade_pipeline,
         pipeline,
     }
 }
@@ -435,13 +970,29 @@ fn create_decay_bind_group(
         .build(device, layout)
 }
 
+fn create_screen_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let dynamic = false;
+    let readonly = true;
+    wgpu::BindGroupLayoutBuilder::new()
+        .storage_buffer(wgpu::ShaderStage::VERTEX, dynamic, readonly)
+        .build(device)
+}
+
+fn create_screen_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    screen_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    wgpu::BindGroupBuilder::new()
+        .buffer_bytes(screen_buffer, 0..SCREEN_BUFFER_SIZE)
+        .build(device, layout)
+}
+
 fn create_pipeline_layout(
     device: &wgpu::Device,
-    bind_group_layout: &wgpu::BindGroupLayout,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
 ) -> wgpu::PipelineLayout {
-    let desc = wgpu::PipelineLayoutDescriptor {
-        bind_group_layouts: &[&bind_group_layout],
-    };
+    let desc = wgpu::PipelineLayoutDescriptor { bind_group_layouts };
     device.create_pipeline_layout(&desc)
 }
 
@@ -460,10 +1011,6 @@ fn create_pipeline(
             0 => Float2,
             1 => Float2
         ])
-        .add_instance_buffer::<Instance>(&wgpu::vertex_attr_array![
-            2 => Float2,
-            3 => Float2
-        ])
         .sample_count(sample_count)
         .build(device)
 }
@@ -510,6 +1057,15 @@ fn create_vertex_buffer(device: &wgpu::Device) -> wgpu::Buffer {
     device.create_buffer_with_data(vertices_bytes, usage)
 }
 
+// Create the storage buffer containing the mode followed by the raw screen bytes of a blank frame.
+fn create_screen_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    let mode = mode_to_u32(&Cbm8032FrameMode::Graphics);
+    let mut screen_bytes = mode.to_ne_bytes().to_vec();
+    screen_bytes.extend_from_slice(&Cbm8032Frame::BLANK_DATA[..]);
+    let usage = wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST;
+    device.create_buffer_with_data(&screen_bytes, usage)
+}
+
 // Create the sampler used for sampling the character sheet image in the fragment shader.
 fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
     wgpu::SamplerBuilder::new()
@@ -518,11 +1074,37 @@ fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
         .build(device)
 }
 
+/// The path to the character sheet image.
+pub fn char_sheet_path(assets: &Path) -> PathBuf {
+    images_path(assets).join(CHAR_SHEET_FILE_NAME)
+}
+
 // Directory in which images are stored.
 fn images_path(assets: &Path) -> PathBuf {
     assets.join("images")
 }
 
+// Directory in which the GLSL shaders are stored, relative to the project's assets directory.
+fn glsl_path(assets: &Path) -> PathBuf {
+    let project = assets.parent().unwrap_or(assets);
+    project.join("src").join("lib").join("glsl")
+}
+
+// The paths of all shaders used by the visualisation.
+fn shader_paths(glsl_path: &Path) -> Vec<PathBuf> {
+    [
+        VERTEX_SHADER_FILE_NAME,
+        FRAGMENT_SHADER_FILE_NAME,
+        DECAY_FRAGMENT_SHADER_FILE_NAME,
+        DECAY_FADE_FRAGMENT_SHADER_FILE_NAME,
+        FULLSCREEN_VERTEX_SHADER_FILE_NAME,
+        CRT_FRAGMENT_SHADER_FILE_NAME,
+    ]
+    .iter()
+    .map(|name| glsl_path.join(name))
+    .collect()
+}
+
 // See the `nannou::wgpu::bytes` documentation for why the following are necessary.
 
 fn vertices_as_bytes(data: &[Vertex]) -> &[u8] {
@@ -533,6 +1115,75 @@ fn uniforms_as_bytes(uniforms: &Uniforms) -> &[u8] {
     unsafe { wgpu::bytes::from(uniforms) }
 }
 
-fn instances_as_bytes(data: &[Instance]) -> &[u8] {
-    unsafe { wgpu::bytes::from_slice(data) }
+fn crt_uniforms_as_bytes(uniforms: &CrtUniforms) -> &[u8] {
+    unsafe { wgpu::bytes::from(uniforms) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // The unsigned integer constants declared by the GLSL source, with any expressions evaluated.
+    fn glsl_constants(source: &str) -> HashMap<String, u32> {
+        let mut constants = HashMap::new();
+        for line in source.lines() {
+            let decl = match line.trim().strip_prefix("const uint ") {
+                Some(decl) => decl.trim_end_matches(';'),
+                None => continue,
+            };
+            let mut parts = decl.splitn(2, '=');
+            let name = parts.next().unwrap().trim().to_string();
+            let expr = parts.next().unwrap();
+            let operand = |token: &str| match token.parse::<u32>() {
+                Ok(value) => value,
+                Err(_) => constants[token],
+            };
+            // Sum the terms, each evaluated left to right.
+            let value = expr
+                .split('+')
+                .map(|term| {
+                    let mut tokens = term.split_whitespace();
+                    let mut value = operand(tokens.next().unwrap());
+                    while let Some(op) = tokens.next() {
+                        let rhs = operand(tokens.next().unwrap());
+                        match op {
+                            "*" => value *= rhs,
+                            "/" => value /= rhs,
+                            _ => panic!("unsupported operator `{}` in `{}`", op, line),
+                        }
+                    }
+                    value
+                })
+                .sum();
+            constants.insert(name, value);
+        }
+        constants
+    }
+
+    #[test]
+    fn vertex_shader_constants_match() {
+        let path = glsl_path(&Path::new(env!("CARGO_MANIFEST_DIR")).join("assets"));
+        let source = std::fs::read_to_string(path.join(VERTEX_SHADER_FILE_NAME)).unwrap();
+        let constants = glsl_constants(&source);
+        let expected = [
+            ("CHARS_PER_LINE", CHARS_PER_LINE as u32),
+            ("DATA_LINES", DATA_LINES as u32),
+            ("BLANK_LINES", BLANK_LINES as u32),
+            ("TOTAL_LINES", TOTAL_LINES as u32),
+            ("DATA_CHARS", CBM_8032_FRAME_DATA_LEN as u32),
+            ("BLANK_BYTE", Cbm8032Frame::BLANK_BYTE as u32),
+            ("CHAR_SHEET_COLS", CHAR_SHEET_COLS as u32),
+            ("CHAR_SHEET_ROWS", CHAR_SHEET_ROWS as u32),
+            ("GRAPHICS_MODE_ROW_OFFSET", GRAPHICS_MODE_ROW_OFFSET as u32),
+            ("TEXT_MODE_ROW_OFFSET", TEXT_MODE_ROW_OFFSET as u32),
+            ("MODE_GRAPHICS", mode_to_u32(&Cbm8032FrameMode::Graphics)),
+        ];
+        for &(name, value) in &expected {
+            let file = VERTEX_SHADER_FILE_NAME;
+            assert_eq!(constants.get(name), Some(&value), "`{}` in {}", name, file);
+        }
+        let first_data_char = (BLANK_LINES / 2) as u32 * CHARS_PER_LINE as u32;
+        assert_eq!(constants["FIRST_DATA_CHAR"], first_data_char);
+    }
 }
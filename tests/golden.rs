@@ -0,0 +1,131 @@
+//! Golden-image tests for the visualisation.
+//!
+//! Canonical frames are rendered via the `raster` reference renderer and compared against the
+//! images checked in to `tests/golden`. To regenerate the images after an intentional change to
+//! the look of the visualisation, run the tests with `UPDATE_GOLDEN=1` and review the diff.
+//!
+//! These images test the CPU reference renderer rather than the GPU pipeline in `vis.rs`, which
+//! cannot run without a graphics device. Drift between the shaders and the reference is only caught
+//! by the check of the vertex shader's constants within `vis.rs`.
+
+use cbm8032_to_vulkan::conf::Config;
+use cbm8032_to_vulkan::raster::Rasteriser;
+use cbm8032_to_vulkan::vis::{self, Cbm8032Frame, Cbm8032FrameMode};
+use nannou::image::{self, GenericImageView};
+use std::path::{Path, PathBuf};
+
+// The maximum difference allowed for any channel of any pixel.
+const TOLERANCE: u8 = 2;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    manifest_dir().join("tests").join("golden").join(format!("{}.png", name))
+}
+
+fn render(frame: &Cbm8032Frame) -> image::RgbaImage {
+    let rasteriser = Rasteriser::open(&manifest_dir().join("assets")).unwrap();
+    let config = Config::default();
+    rasteriser.render(frame, &config, rasteriser.native_size())
+}
+
+// Compare the image against the golden image of the given name, or update the golden image if
+// `UPDATE_GOLDEN` is set.
+fn assert_golden(name: &str, image: &image::RgbaImage) {
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(&path).unwrap();
+        return;
+    }
+    let golden = image::open(&path)
+        .unwrap_or_else(|err| panic!("failed to open {}: {}", path.display(), err))
+        .to_rgba();
+    assert_eq!(golden.dimensions(), image.dimensions(), "{}: size differs", name);
+    let mismatches = golden
+        .pixels()
+        .zip(image.pixels())
+        .filter(|(a, b)| a.0.iter().zip(&b.0).any(|(a, b)| a.max(b) - a.min(b) > TOLERANCE))
+        .count();
+    assert_eq!(mismatches, 0, "{}: {} pixels differ from {}", name, mismatches, path.display());
+}
+
+// A frame in which the data begins with every byte value in ascending order.
+fn every_byte(mode: Cbm8032FrameMode) -> Cbm8032Frame {
+    let mut frame = Cbm8032Frame::blank_graphics();
+    frame.mode = mode;
+    for (ix, byte) in frame.data.iter_mut().take(256).enumerate() {
+        *byte = ix as u8;
+    }
+    frame
+}
+
+// A frame with the given lines of text, converted from ASCII to PET screen codes.
+fn text_screen(mode: Cbm8032FrameMode, lines: &[&str]) -> Cbm8032Frame {
+    let mut frame = Cbm8032Frame::blank_graphics();
+    frame.mode = mode;
+    for (line_ix, line) in lines.iter().enumerate() {
+        let start = line_ix * vis::CHARS_PER_LINE as usize;
+        for (ix, ch) in line.bytes().enumerate() {
+            frame.data[start + ix] = match ch {
+                b'@'..=b'Z' => ch - b'@',
+                _ => ch,
+            };
+        }
+    }
+    frame
+}
+
+#[test]
+fn blank() {
+    assert_golden("blank", &render(&Cbm8032Frame::blank_graphics()));
+}
+
+#[test]
+fn every_byte_graphics() {
+    assert_golden("every_byte_graphics", &render(&every_byte(Cbm8032FrameMode::Graphics)));
+}
+
+#[test]
+fn every_byte_text() {
+    assert_golden("every_byte_text", &render(&every_byte(Cbm8032FrameMode::Text)));
+}
+
+#[test]
+fn test_graphics() {
+    // All but the first 16 bytes of the test frame are random, so only their cells are compared.
+    let image = render(&Cbm8032Frame::_test_graphics());
+    let cell_w = image.width() / vis::CHARS_PER_LINE as u32;
+    let cell_h = image.height() / vis::TOTAL_LINES as u32;
+    let first_cells = image.view(0, cell_h, 16 * cell_w, cell_h).to_image();
+    assert_golden("test_graphics_first_cells", &first_cells);
+}
+
+#[test]
+fn ready_screen() {
+    let lines = [
+        "*** COMMODORE BASIC 4.0 ***",
+        "",
+        " 31743 BYTES FREE",
+        "",
+        "READY.",
+    ];
+    assert_golden("ready", &render(&text_screen(Cbm8032FrameMode::Graphics, &lines)));
+}
+
+#[test]
+fn stale_buffers() {
+    let lines = ["10 PRINT \"HELLO\"", "20 GOTO 10", "RUN"];
+    let mut frame = text_screen(Cbm8032FrameMode::Graphics, &lines);
+    // Mark the first half of the first line stale.
+    frame.stale = 0b01;
+    let rasteriser = Rasteriser::open(&manifest_dir().join("assets")).unwrap();
+    let config = Config {
+        show_stale_buffers: true,
+        ..Default::default()
+    };
+    let image = rasteriser.render(&frame, &config, rasteriser.native_size());
+    assert_golden("stale", &image);
+}